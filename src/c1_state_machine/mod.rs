@@ -28,13 +28,29 @@ pub trait StateMachine {
     }
 }
 
+/// Drive any state machine from the given starting state through each of the transitions in
+/// turn, returning the final state
+pub fn run<M: StateMachine>(
+    start: M::State,
+    transitions: impl IntoIterator<Item = M::Transition>,
+) -> M::State {
+    transitions
+        .into_iter()
+        .fold(start, |state, t| M::next_state(&state, &t))
+}
+
 /// A set of play users for experimenting with the multi-user state machines
-#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy)]
+#[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Debug, Clone, Copy)]
 pub enum User {
     Alice,
     Bob,
     Charlie,
 }
 
+impl User {
+    /// Every play user, in order
+    pub const ALL: [User; 3] = [User::Alice, User::Bob, User::Charlie];
+}
+
 // TODO Some kind of main program that allows users to interact with their state machine in a
 // repl-like way. Might require From<String> implementation for the transition type.
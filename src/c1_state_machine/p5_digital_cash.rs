@@ -4,7 +4,13 @@
 //! When a state transition spends bills, new bills are created in lesser or equal amount.
 
 use super::{StateMachine, User};
-use std::collections::HashSet;
+use crate::hash;
+use std::{
+    cell::Cell,
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    hash::{Hash, Hasher},
+};
 
 /// This state machine models a multi-user currency system. It tracks a set of bills in
 /// circulation, and updates that set when money is transferred.
@@ -13,11 +19,210 @@ pub struct DigitalCashSystem;
 /// A single bill in the digital cash system. Each bill has an owner who is allowed to spent
 /// it and an amount that it is worth. It also has serial number to ensure that each bill
 /// is unique.
-#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+///
+/// A bill also remembers the serials of the bills that were spent to fund it, the epochs at
+/// which it was created and at which it expires, and optionally a condition that must be met
+/// to spend it. These are metadata only and do not take part in a bill's identity, so two
+/// bills are equal when their owner, amount and serial are.
+#[derive(Clone, Debug)]
 pub struct Bill {
     owner: User,
     amount: u64,
     serial: u64,
+    /// The serials of the bills spent to create this one. Empty for minted bills.
+    parents: Vec<u64>,
+    /// The epoch in which this bill was created
+    minted_at: u64,
+    /// The epoch from which this bill can no longer be spent
+    expires_at: u64,
+    /// Who may spend this bill, and when. Bills without a condition can be spent by any
+    /// transfer; bills with one can only be spent by a signed transfer that satisfies it.
+    spend_condition: Option<SpendCondition>,
+}
+
+/// A condition that must be met by the signers of a transfer for it to spend a bill
+#[derive(Clone, Debug, Hash)]
+pub enum SpendCondition {
+    /// The given user must sign
+    Owned(User),
+    /// Any one of the given users must sign
+    AnyOf(Vec<User>),
+    /// The bill's owner must sign, and not before the given epoch
+    Timelock(u64),
+}
+
+impl SpendCondition {
+    /// Whether a transfer signed by the given users at the given epoch may spend a bill
+    /// owned by `owner` that carries this condition
+    pub fn permits(&self, owner: &User, signers: &[User], epoch: u64) -> bool {
+        match self {
+            SpendCondition::Owned(user) => signers.contains(user),
+            SpendCondition::AnyOf(users) => users.iter().any(|user| signers.contains(user)),
+            SpendCondition::Timelock(height) => epoch >= *height && signers.contains(owner),
+        }
+    }
+
+    /// This condition with every mention of the `old` user replaced by `new`
+    fn rekeyed(self, old: &User, new: &User) -> Self {
+        let rekey = |user: User| if user == *old { *new } else { user };
+        match self {
+            SpendCondition::Owned(user) => SpendCondition::Owned(rekey(user)),
+            SpendCondition::AnyOf(users) => {
+                SpendCondition::AnyOf(users.into_iter().map(rekey).collect())
+            }
+            SpendCondition::Timelock(height) => SpendCondition::Timelock(height),
+        }
+    }
+}
+
+impl Bill {
+    pub fn new(owner: User, amount: u64, serial: u64) -> Self {
+        Bill {
+            owner,
+            amount,
+            serial,
+            parents: Vec::new(),
+            minted_at: 0,
+            expires_at: u64::MAX,
+            spend_condition: None,
+        }
+    }
+
+    /// This bill, spendable only by a signed transfer satisfying the given condition
+    pub fn with_condition(self, condition: SpendCondition) -> Self {
+        Bill {
+            spend_condition: Some(condition),
+            ..self
+        }
+    }
+
+    /// This bill with every mention of the `old` user, as owner or in the spend condition,
+    /// replaced by `new`
+    fn rekeyed(self, old: &User, new: &User) -> Self {
+        Bill {
+            owner: if self.owner == *old { *new } else { self.owner },
+            spend_condition: self
+                .spend_condition
+                .map(|condition| condition.rekeyed(old, new)),
+            ..self
+        }
+    }
+
+    /// This bill, set to expire once the system reaches the given epoch
+    pub fn expiring_at(self, epoch: u64) -> Self {
+        Bill {
+            expires_at: epoch,
+            ..self
+        }
+    }
+}
+
+impl PartialEq for Bill {
+    fn eq(&self, other: &Self) -> bool {
+        self.owner == other.owner && self.amount == other.amount && self.serial == other.serial
+    }
+}
+
+impl Eq for Bill {}
+
+impl Hash for Bill {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.owner.hash(state);
+        self.amount.hash(state);
+        self.serial.hash(state);
+    }
+}
+
+/// Bills are ordered by serial first, so sorted collections of bills iterate in the order
+/// the bills were created.
+impl Ord for Bill {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.serial, self.owner, self.amount).cmp(&(other.serial, other.owner, other.amount))
+    }
+}
+
+impl PartialOrd for Bill {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// How many bits a [`BloomFilter`] has
+pub const BLOOM_BITS: usize = 1024;
+
+/// How many bits a [`BloomFilter`] sets for each serial
+pub const BLOOM_HASHES: usize = 3;
+
+/// A probabilistic set of serials. It never forgets a serial that was inserted, but may
+/// claim to contain serials that were not, so only a negative answer is definitive.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+}
+
+impl BloomFilter {
+    pub fn new() -> Self {
+        BloomFilter {
+            bits: vec![0; BLOOM_BITS / 64],
+        }
+    }
+
+    /// The positions of the bits that represent the given serial
+    fn positions(serial: u64) -> impl Iterator<Item = usize> {
+        (0..BLOOM_HASHES).map(move |i| hash(&(i, serial)) as usize % BLOOM_BITS)
+    }
+
+    pub fn insert(&mut self, serial: u64) {
+        for position in Self::positions(serial) {
+            self.bits[position / 64] |= 1 << (position % 64);
+        }
+    }
+
+    /// Whether the serial may have been inserted. `false` means it definitely was not.
+    pub fn might_contain(&self, serial: u64) -> bool {
+        Self::positions(serial)
+            .all(|position| self.bits[position / 64] & (1 << (position % 64)) != 0)
+    }
+}
+
+impl Default for BloomFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The reasons the serial counter cannot be moved
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SerialError {
+    /// The bill with this serial is still in circulation, and the counter would reach it again
+    WouldReuseSerial { serial: u64 },
+}
+
+/// A bill co-owned by several users. Each co-owner holds a whole number of shares, and the
+/// bill is worth the total of all the shares. A co-owner can exit by moving their shares out
+/// into a bill of their own.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct SharedBill {
+    /// Each co-owner and the number of shares they hold
+    shares: Vec<(User, u64)>,
+    serial: u64,
+}
+
+impl SharedBill {
+    /// The total value of the bill
+    pub fn amount(&self) -> u64 {
+        self.shares
+            .iter()
+            .fold(0, |total, (_, shares)| total.saturating_add(*shares))
+    }
+
+    /// The number of shares the given user holds in this bill
+    pub fn share_of(&self, user: &User) -> u64 {
+        self.shares
+            .iter()
+            .filter(|(owner, _)| owner == user)
+            .fold(0, |total, (_, shares)| total.saturating_add(*shares))
+    }
 }
 
 /// The State of a digital cash system. Primarily just the set of currently circulating bills.,
@@ -28,6 +233,161 @@ pub struct State {
     bills: HashSet<Bill>,
     /// The next serial number to use when a bill is created.
     next_serial: u64,
+    /// The serial the first bill of this state was numbered with
+    serial_base: u64,
+    /// The total value destroyed by explicit burns and expiry
+    total_burned: u64,
+    /// The current epoch, advanced by ticks
+    epoch: u64,
+    /// The epoch up to which demurrage has been charged on every bill
+    demurrage_charged_at: u64,
+    /// Whether the system is paused. No value can move while it is.
+    paused: bool,
+    /// The user allowed to pause and unpause the system, if anyone
+    pause_authority: Option<User>,
+    /// Whether transfers may only create bills up to the largest bill they spend
+    forbid_recombination: bool,
+    /// Whether users are held to [`MAX_WINDOW_SPEND`]
+    limit_window_spend: bool,
+    /// Whether receives listed out of serial order are reported as such
+    strict_receive_order: bool,
+    /// Whether transfers pay [`TAX_BPS`] of what they spend as a tax
+    transfer_tax: bool,
+    /// The users allowed to mint. Anyone may mint while this is empty.
+    authorities: HashSet<User>,
+    /// Which chain this state belongs to, telling apart forks that share a history
+    chain_id: u32,
+    /// Transfer surplus captured by pooled transfers and available for refunds
+    burn_pool: u64,
+    /// Bills locked in escrow, keyed by serial
+    escrows: HashMap<u64, Escrow>,
+    /// How many mints each user has made in the current epoch
+    mints_this_epoch: HashMap<User, u64>,
+    /// Bills co-owned by several users, keyed by serial
+    shared_bills: HashMap<u64, SharedBill>,
+    /// How much each owner has approved each spender to spend on their behalf, keyed by
+    /// (owner, spender)
+    allowances: HashMap<(User, User), u64>,
+    /// The value each user has sent away in recent epochs, as (epoch, amount) pairs, oldest
+    /// first. Entries leave once they are older than [`SPEND_WINDOW`].
+    recent_spends: HashMap<User, VecDeque<(u64, u64)>>,
+    /// New money minted automatically at every tick, if any
+    issuance: Option<IssuanceSchedule>,
+}
+
+/// A fixed amount of new money minted to a treasury at the start of every epoch
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct IssuanceSchedule {
+    /// The user who receives the new money
+    pub treasury: User,
+    /// How much is minted each epoch. Amounts above [`MAX_BILL_AMOUNT`] are split across
+    /// several bills.
+    pub per_epoch: u64,
+}
+
+/// A bill locked away until its claimant reveals the secret, or until it times out and its
+/// original owner takes it back
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Escrow {
+    /// The locked bill
+    bill: Bill,
+    /// The user who may claim the bill by revealing the secret
+    claimant: User,
+    /// The hash of the secret, as computed by [`hash32`]
+    secret_hash: [u8; 32],
+    /// The epoch the bill was locked at
+    locked_at: u64,
+}
+
+/// The most a single newly created bill may be worth. Larger amounts must be split across
+/// several bills.
+pub const MAX_BILL_AMOUNT: u64 = 1_000_000_000_000;
+
+/// The most bills a transfer may create for every bill it spends
+pub const FANOUT_FACTOR: usize = 4;
+
+/// The longest memo, in bytes, that a transfer may carry
+pub const MAX_MEMO_LEN: usize = 32;
+
+/// How many mints a single user may make within one epoch
+pub const MAX_MINTS_PER_EPOCH: u64 = 3;
+
+/// The denominator of rates given in basis points: a rate of this many basis points is the
+/// whole amount
+pub const BASIS_POINTS: u64 = 10_000;
+
+/// The share of the value spent by a transfer that is burned as a tax when the tax is on, in
+/// [`BASIS_POINTS`]
+pub const TAX_BPS: u64 = 100;
+
+/// How many epochs, counting the current one, make up the window that
+/// [`MAX_WINDOW_SPEND`] applies to
+pub const SPEND_WINDOW: u64 = 3;
+
+/// The most value a single user may send away across the last [`SPEND_WINDOW`] epochs, when
+/// the limit is on. Value a transfer returns to its spender as change does not count.
+pub const MAX_WINDOW_SPEND: u64 = 1_000;
+
+/// How many epochs an escrowed bill stays claimable before its owner can reclaim it
+pub const ESCROW_TIMEOUT: u64 = 10;
+
+/// A 32 byte digest of the given data, built from several salted runs of the crate's simple
+/// hasher. Good enough for teaching hash locks, but not cryptographically secure.
+pub fn hash32(data: &[u8]) -> [u8; 32] {
+    digest32(&data)
+}
+
+/// The identifier of a transaction: a 32 byte digest of its contents, as for [`hash32`].
+/// Identical transactions share an identifier.
+pub fn tx_id(t: &CashTransaction) -> [u8; 32] {
+    digest32(t)
+}
+
+/// The path from a bill up to a [`State::merkle_root`]: the sibling digest at each level of
+/// the tree, bottom first, along with whether that sibling sits on the left.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MerkleProof {
+    siblings: Vec<([u8; 32], bool)>,
+}
+
+/// Check that the proof places the bill in the tree with the given root, without needing
+/// any of the other bills
+pub fn verify_inclusion(root: [u8; 32], bill: &Bill, proof: &MerkleProof) -> bool {
+    let computed = proof
+        .siblings
+        .iter()
+        .fold(digest32(bill), |node, (sibling, on_left)| {
+            if *on_left {
+                digest32(&(sibling, node))
+            } else {
+                digest32(&(node, sibling))
+            }
+        });
+    computed == root
+}
+
+/// Hash each pair of nodes into their parent. A lone last node is paired with itself.
+fn merkle_parents(level: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    level
+        .chunks(2)
+        .map(|pair| digest32(&(pair[0], pair[pair.len() - 1])))
+        .collect()
+}
+
+/// The entries of a hash map in ascending key order, so that hashing them does not depend on
+/// the map's iteration order
+fn sorted_entries<K: Copy + Ord, V>(map: &HashMap<K, V>) -> Vec<(K, &V)> {
+    let mut entries: Vec<(K, &V)> = map.iter().map(|(key, value)| (*key, value)).collect();
+    entries.sort_by_key(|(key, _)| *key);
+    entries
+}
+
+fn digest32<T: Hash + ?Sized>(t: &T) -> [u8; 32] {
+    let mut digest = [0u8; 32];
+    for (i, chunk) in digest.chunks_mut(8).enumerate() {
+        chunk.copy_from_slice(&hash(&(i, t)).to_le_bytes());
+    }
+    digest
 }
 
 impl State {
@@ -35,727 +395,8255 @@ impl State {
         State {
             bills: HashSet::<Bill>::new(),
             next_serial: 0,
+            serial_base: 0,
+            total_burned: 0,
+            epoch: 0,
+            demurrage_charged_at: 0,
+            paused: false,
+            pause_authority: None,
+            forbid_recombination: false,
+            limit_window_spend: false,
+            strict_receive_order: false,
+            transfer_tax: false,
+            authorities: HashSet::new(),
+            chain_id: 0,
+            burn_pool: 0,
+            escrows: HashMap::new(),
+            mints_this_epoch: HashMap::new(),
+            shared_bills: HashMap::new(),
+            allowances: HashMap::new(),
+            recent_spends: HashMap::new(),
+            issuance: None,
+        }
+    }
+
+    /// An empty state in which only the given users may mint new money, whether by minting,
+    /// minting and sending or airdropping
+    pub fn with_authorities(authorities: impl IntoIterator<Item = User>) -> Self {
+        State {
+            authorities: authorities.into_iter().collect(),
+            ..State::new()
+        }
+    }
+
+    /// Reject minting signed by a user who is not an authority, unless minting is open to
+    /// everyone
+    fn check_authority(&self, signer: &User) -> Result<(), TransferError> {
+        if self.authorities.is_empty() || self.authorities.contains(signer) {
+            Ok(())
+        } else {
+            Err(TransferError::Unauthorized)
+        }
+    }
+
+    /// An empty state whose first bill will be numbered `base`, leaving the serials below it
+    /// unused, for example to reserve them for another system.
+    pub fn new_with_serial_base(base: u64) -> Self {
+        State {
+            next_serial: base,
+            serial_base: base,
+            ..State::new()
         }
     }
 
-    pub fn set_serial(&mut self, serial: u64) {
+    /// The value captured from pooled transfers that has not yet been refunded
+    pub fn burn_pool(&self) -> u64 {
+        self.burn_pool
+    }
+
+    /// Designate the user allowed to pause and unpause the system
+    pub fn set_pause_authority(&mut self, authority: User) {
+        self.pause_authority = Some(authority);
+    }
+
+    /// Choose whether a transfer may receive a bill worth more than the largest bill it
+    /// spends. Forbidding it stops value being recombined into ever larger bills by
+    /// ordinary transfers. It is allowed by default.
+    pub fn set_forbid_recombination(&mut self, forbid: bool) {
+        self.forbid_recombination = forbid;
+    }
+
+    /// Choose whether each user may send away at most [`MAX_WINDOW_SPEND`] across the last
+    /// [`SPEND_WINDOW`] epochs. Spends are only recorded while the limit is on. It is off by
+    /// default.
+    pub fn set_limit_window_spend(&mut self, limit: bool) {
+        self.limit_window_spend = limit;
+    }
+
+    /// Mint new money on every tick according to the given schedule, replacing any earlier
+    /// schedule. A tick is rejected as [`TransferError::SupplyOverflow`] once its issuance
+    /// would overflow the total supply.
+    pub fn set_issuance_schedule(&mut self, schedule: IssuanceSchedule) {
+        self.issuance = Some(schedule);
+    }
+
+    /// Choose whether transfers must list their receives in ascending serial order, the one
+    /// canonical encoding. Out of order receives always fail the contiguity check, but in
+    /// strict mode they are rejected as [`TransferError::UnorderedReceives`] first. It is off
+    /// by default.
+    pub fn set_strict_receive_order(&mut self, strict: bool) {
+        self.strict_receive_order = strict;
+    }
+
+    /// Choose whether every transfer, plain, signed or pooled, must leave [`TAX_BPS`] of the
+    /// value it spends unreceived, to be burned as a tax. It is off by default.
+    pub fn set_transfer_tax(&mut self, tax: bool) {
+        self.transfer_tax = tax;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// The total value destroyed by explicit burns and expiry
+    pub fn total_burned(&self) -> u64 {
+        self.total_burned
+    }
+
+    /// Set the id of the chain this state belongs to. Signed transfers only apply to states
+    /// with the chain id they were signed for.
+    pub fn set_chain_id(&mut self, chain_id: u32) {
+        self.chain_id = chain_id;
+    }
+
+    pub fn chain_id(&self) -> u32 {
+        self.chain_id
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// How many epochs ago the circulating bill with the given serial was created, or `None`
+    /// if no such bill is in circulation
+    pub fn bill_age(&self, serial: u64) -> Option<u64> {
+        self.bills
+            .iter()
+            .find(|bill| bill.serial == serial)
+            .map(|bill| self.epoch.saturating_sub(bill.minted_at))
+    }
+
+    /// Set the serial to number the next bill with. Values at or below the serial of a bill
+    /// still in circulation are rejected, since the counter would hand that serial out again.
+    pub fn set_serial(&mut self, serial: u64) -> Result<(), SerialError> {
+        if let Some(highest) = self.highest_circulating_serial() {
+            if serial <= highest {
+                return Err(SerialError::WouldReuseSerial { serial: highest });
+            }
+        }
         self.next_serial = serial;
+        Ok(())
     }
 
     pub fn next_serial(&self) -> u64 {
         self.next_serial
     }
 
-    fn increment_serial(&mut self) {
-        self.next_serial += 1
+    /// The highest serial ever assigned to a bill, or `None` if no bill has ever been created
+    pub fn highest_serial(&self) -> Option<u64> {
+        self.next_serial
+            .checked_sub(1)
+            .filter(|serial| *serial >= self.serial_base)
+    }
+
+    /// The highest serial of any bill still in circulation, including co-owned and escrowed
+    /// bills
+    fn highest_circulating_serial(&self) -> Option<u64> {
+        self.bills
+            .iter()
+            .map(|bill| bill.serial)
+            .chain(self.shared_bills.keys().copied())
+            .chain(self.escrows.keys().copied())
+            .max()
+    }
+
+    /// Check that the serial counter is past every circulating serial, as it always is unless
+    /// it has been moved by hand. Returns the lowest circulating serial that is not below
+    /// the counter, which a future bill could collide with.
+    pub fn check_serial_consistency(&self) -> Result<(), u64> {
+        let offending = self
+            .bills
+            .iter()
+            .map(|bill| bill.serial)
+            .chain(self.shared_bills.keys().copied())
+            .chain(self.escrows.keys().copied())
+            .filter(|serial| *serial >= self.next_serial)
+            .min();
+        match offending {
+            Some(serial) => Err(serial),
+            None => Ok(()),
+        }
+    }
+
+    /// A bloom filter over the serials of the circulating bills. Anyone holding the filter can
+    /// tell for certain that a serial is not in circulation, without seeing the bills.
+    pub fn serial_bloom(&self) -> BloomFilter {
+        let mut bloom = BloomFilter::new();
+        for bill in self.bills.iter() {
+            bloom.insert(bill.serial);
+        }
+        bloom
+    }
+
+    /// The serials of the bills circulating here but not in `other`, followed by those
+    /// circulating in `other` but not here, both in ascending order. Only which serials exist
+    /// is compared, not who owns them or what they are worth.
+    pub fn serial_diff(&self, other: &State) -> (Vec<u64>, Vec<u64>) {
+        let ours: BTreeSet<u64> = self.bills.iter().map(|bill| bill.serial).collect();
+        let theirs: BTreeSet<u64> = other.bills.iter().map(|bill| bill.serial).collect();
+        (
+            ours.difference(&theirs).copied().collect(),
+            theirs.difference(&ours).copied().collect(),
+        )
+    }
+
+    /// A copy of this state in which every circulating bill, including co-owned and escrowed
+    /// ones, is renumbered densely from zero in the order of its old serial, along with the
+    /// mapping from old serials to new. Owners and amounts are kept, but the record of each
+    /// bill's parents is dropped, since it would link the new serials back to the old.
+    pub fn reserial(&self) -> (State, HashMap<u64, u64>) {
+        let mut serials: Vec<u64> = self
+            .bills
+            .iter()
+            .map(|bill| bill.serial)
+            .chain(self.shared_bills.keys().copied())
+            .chain(self.escrows.keys().copied())
+            .collect();
+        serials.sort();
+        let mapping: HashMap<u64, u64> = serials.into_iter().zip(0..).collect();
+        let renumber = |bill: &Bill| Bill {
+            serial: mapping[&bill.serial],
+            parents: Vec::new(),
+            ..bill.clone()
+        };
+
+        let state = State {
+            bills: self.bills.iter().map(renumber).collect(),
+            next_serial: mapping.len() as u64,
+            serial_base: 0,
+            shared_bills: self
+                .shared_bills
+                .values()
+                .map(|shared| {
+                    let serial = mapping[&shared.serial];
+                    let renumbered = SharedBill {
+                        serial,
+                        ..shared.clone()
+                    };
+                    (serial, renumbered)
+                })
+                .collect(),
+            escrows: self
+                .escrows
+                .values()
+                .map(|escrow| {
+                    let renumbered = Escrow {
+                        bill: renumber(&escrow.bill),
+                        ..escrow.clone()
+                    };
+                    (renumbered.bill.serial, renumbered)
+                })
+                .collect(),
+            ..self.clone()
+        };
+        (state, mapping)
+    }
+
+    /// A snapshot of the serial counter that can later be passed to
+    /// [`restore_serial`](State::restore_serial)
+    pub fn serial_checkpoint(&self) -> u64 {
+        self.next_serial
+    }
+
+    /// Move the serial counter back to a checkpoint. Just like with
+    /// [`set_serial`](State::set_serial), values that would number a new bill with the serial
+    /// of one still in circulation are rejected.
+    pub fn restore_serial(&mut self, value: u64) -> Result<(), SerialError> {
+        self.set_serial(value)
+    }
+
+    /// How much the user has sent away within the current spend window
+    fn window_spend(&self, user: &User) -> u64 {
+        self.recent_spends.get(user).map_or(0, |spends| {
+            spends
+                .iter()
+                .filter(|(epoch, _)| epoch.saturating_add(SPEND_WINDOW) > self.epoch)
+                .fold(0, |total: u64, (_, amount)| total.saturating_add(*amount))
+        })
+    }
+
+    /// Drop the recorded spends that have left the spend window
+    fn forget_old_spends(&mut self) {
+        let epoch = self.epoch;
+        for spends in self.recent_spends.values_mut() {
+            while spends
+                .front()
+                .is_some_and(|(spent_at, _)| spent_at.saturating_add(SPEND_WINDOW) <= epoch)
+            {
+                spends.pop_front();
+            }
+        }
+        self.recent_spends.retain(|_, spends| !spends.is_empty());
+    }
+
+    /// Move on to the next serial, failing once the serial space is used up
+    fn increment_serial(&mut self) -> Result<(), TransferError> {
+        self.next_serial = self
+            .next_serial
+            .checked_add(1)
+            .ok_or(TransferError::SerialSpaceExhausted)?;
+        Ok(())
+    }
+
+    fn add_bill(&mut self, elem: Bill) -> Result<(), TransferError> {
+        self.increment_serial()?;
+        self.bills.insert(Bill {
+            minted_at: self.epoch,
+            ..elem
+        });
+        Ok(())
+    }
+
+    /// The total value held by users that are treated as unable to spend, such as burn
+    /// addresses. This value still counts toward the total supply even though it can never
+    /// move again.
+    pub fn unspendable_value(&self, burn_addresses: &HashSet<User>) -> u64 {
+        self.bills
+            .iter()
+            .filter(|bill| burn_addresses.contains(&bill.owner))
+            .fold(0, |total, bill| total.saturating_add(bill.amount))
+    }
+
+    /// All circulating bills, largest first. Bills of equal amount are ordered by serial.
+    pub fn bills_by_amount(&self) -> Vec<Bill> {
+        let mut bills: Vec<Bill> = self.bills.iter().cloned().collect();
+        bills.sort_by(|a, b| b.amount.cmp(&a.amount).then(a.serial.cmp(&b.serial)));
+        bills
+    }
+
+    /// The largest amount the given user can spend in a single transfer that spends at most
+    /// `max_inputs` bills, which is the total of their largest `max_inputs` bills. Shares in
+    /// co-owned bills cannot be spent directly, so they do not count.
+    pub fn max_single_transfer(&self, user: &User, max_inputs: usize) -> u64 {
+        self.bills_by_amount()
+            .iter()
+            .filter(|bill| bill.owner == *user)
+            .take(max_inputs)
+            .fold(0, |total, bill| total.saturating_add(bill.amount))
+    }
+
+    /// The bills of the given user that make up the most valuable transfer that burns nothing,
+    /// along with their total. Such a transfer spends at most [`MAX_INPUTS`] bills and must
+    /// pass all of their value on in at most `max_outputs` bills, none worth more than
+    /// [`MAX_BILL_AMOUNT`]. Returns `None` if no bill of the user fits within those caps.
+    ///
+    /// Every combination of the candidate bills is tried, so to keep the search cheap only
+    /// the user's [`MAX_COMBINATION_BILLS`] largest bills that fit within the caps are
+    /// candidates. A better total made of smaller bills may be missed when the caps bind.
+    pub fn max_conserving_transfer(
+        &self,
+        from: &User,
+        max_outputs: usize,
+    ) -> Option<(Vec<Bill>, u64)> {
+        // try every combination of up to `inputs_left` more bills, keeping the best total
+        fn search(
+            bills: &[&Bill],
+            inputs_left: usize,
+            budget: u64,
+            chosen: &mut Vec<usize>,
+            total: u64,
+            best: &mut Option<(Vec<usize>, u64)>,
+            start: usize,
+        ) {
+            if total > 0 && best.as_ref().is_none_or(|(_, value)| total > *value) {
+                *best = Some((chosen.clone(), total));
+            }
+            if inputs_left == 0 {
+                return;
+            }
+            for i in start..bills.len() {
+                let Some(next) = total.checked_add(bills[i].amount) else {
+                    continue;
+                };
+                if next > budget {
+                    continue;
+                }
+                chosen.push(i);
+                search(bills, inputs_left - 1, budget, chosen, next, best, i + 1);
+                chosen.pop();
+            }
+        }
+
+        let budget = MAX_BILL_AMOUNT.saturating_mul(max_outputs as u64);
+        let mut bills: Vec<Bill> = self
+            .bills_by_amount()
+            .into_iter()
+            .filter(|bill| bill.owner == *from && bill.amount <= budget)
+            .take(MAX_COMBINATION_BILLS)
+            .collect();
+        bills.sort();
+        let bills: Vec<&Bill> = bills.iter().collect();
+        let mut best = None;
+        search(&bills, MAX_INPUTS, budget, &mut Vec::new(), 0, &mut best, 0);
+        best.map(|(chosen, total)| (chosen.iter().map(|&i| bills[i].clone()).collect(), total))
+    }
+
+    /// Every set of the owner's bills that adds up to exactly the given amount, each in serial
+    /// order. All `2^n` subsets of the owner's `n` bills are tried, so only the first
+    /// [`MAX_COMBINATION_BILLS`] of them in serial order are considered.
+    pub fn all_change_combinations(&self, owner: &User, amount: u64) -> Vec<Vec<Bill>> {
+        let bills: Vec<&Bill> = self
+            .bills_sorted()
+            .into_iter()
+            .filter(|bill| bill.owner == *owner)
+            .take(MAX_COMBINATION_BILLS)
+            .collect();
+        (1u32..1 << bills.len())
+            .map(|mask| {
+                bills
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| mask & (1 << i) != 0)
+                    .map(|(_, bill)| (*bill).clone())
+                    .collect::<Vec<Bill>>()
+            })
+            .filter(|subset| {
+                subset
+                    .iter()
+                    .try_fold(0u64, |total, bill| total.checked_add(bill.amount))
+                    == Some(amount)
+            })
+            .collect()
+    }
+
+    /// Whether any circulating bill is worth nothing. Transitions never create such bills, but
+    /// a state built directly from bills may contain them.
+    pub fn has_zero_value_bills(&self) -> bool {
+        self.bills.iter().any(|bill| bill.amount == 0)
+    }
+
+    /// Remove every circulating bill that is worth nothing, returning how many were removed
+    pub fn purge_zero_value(&mut self) -> usize {
+        let before = self.bills.len();
+        self.bills.retain(|bill| bill.amount > 0);
+        before - self.bills.len()
+    }
+
+    /// How much the owner has approved the spender to spend on their behalf
+    pub fn allowance(&self, owner: &User, spender: &User) -> u64 {
+        self.allowances
+            .get(&(*owner, *spender))
+            .copied()
+            .unwrap_or(0)
     }
 
-    fn add_bill(&mut self, elem: Bill) {
-        self.bills.insert(elem);
-        self.increment_serial()
-    }
+    /// The part of the ledger that concerns the given user, as a wallet would see it. Only
+    /// the user's own bills are kept, along with the co-owned bills they hold shares in, the
+    /// escrows they lock or may claim and the allowances they give or receive. The serial
+    /// counter, epoch and other system-wide settings are preserved, so the sub-state is
+    /// consistent on its own.
+    pub fn sub_state_for(&self, user: &User) -> State {
+        let mut sub_state = self.clone();
+        sub_state.bills.retain(|bill| bill.owner == *user);
+        sub_state
+            .shared_bills
+            .retain(|_, shared| shared.share_of(user) > 0);
+        sub_state
+            .escrows
+            .retain(|_, escrow| escrow.bill.owner == *user || escrow.claimant == *user);
+        sub_state
+            .mints_this_epoch
+            .retain(|minter, _| minter == user);
+        sub_state
+            .allowances
+            .retain(|(owner, spender), _| owner == user || spender == user);
+        sub_state
+    }
+
+    /// The bills owned by the given user in ascending serial order
+    fn owned_bills(&self, user: &User) -> Vec<Bill> {
+        let mut bills: Vec<Bill> = self
+            .bills
+            .iter()
+            .filter(|bill| bill.owner == *user)
+            .cloned()
+            .collect();
+        bills.sort();
+        bills
+    }
+
+    /// Turn an escrowed bill back into a circulating bill owned by the given user. The new
+    /// bill keeps the escrowed bill's expiry, so escrow cannot be used to outlive it.
+    fn release_escrow(&mut self, escrow: Escrow, to: User) -> Result<(), TransferError> {
+        let bill = Bill {
+            parents: vec![escrow.bill.serial],
+            expires_at: escrow.bill.expires_at,
+            ..Bill::new(to, escrow.bill.amount, self.next_serial)
+        };
+        self.add_bill(bill)
+    }
+
+    /// The total value of the bills locked in escrow
+    pub fn escrowed_value(&self) -> u64 {
+        self.escrows
+            .values()
+            .fold(0, |total, escrow| total.saturating_add(escrow.bill.amount))
+    }
+
+    /// The users holding at least one bill, in ascending order
+    pub fn holders(&self) -> Vec<User> {
+        let mut holders: Vec<User> = self.bills.iter().map(|bill| bill.owner).collect();
+        holders.sort();
+        holders.dedup();
+        holders
+    }
+
+    /// The distinct amounts of the circulating bills, in ascending order
+    pub fn denominations(&self) -> Vec<u64> {
+        let mut denominations: Vec<u64> = self.bills.iter().map(|bill| bill.amount).collect();
+        denominations.sort();
+        denominations.dedup();
+        denominations
+    }
+
+    /// How many other circulating bills are worth exactly as much as the bill with the given
+    /// serial, and so could be mistaken for it by someone who only sees amounts. The bill
+    /// itself is not counted, so a bill with a unique amount has an anonymity set of zero, as
+    /// does a serial that is not in circulation.
+    pub fn anonymity_set(&self, serial: u64) -> usize {
+        let Some(target) = self.bills.iter().find(|bill| bill.serial == serial) else {
+            return 0;
+        };
+        self.bills
+            .iter()
+            .filter(|bill| bill.amount == target.amount && bill.serial != serial)
+            .count()
+    }
+
+    /// The circulating bills that were created at least `min_age` epochs before
+    /// `current_epoch`, in ascending serial order. Spending a bill always replaces it with new
+    /// ones, so these bills have sat untouched for at least that long.
+    pub fn never_spent(&self, current_epoch: u64, min_age: u64) -> Vec<Bill> {
+        self.bills_sorted()
+            .into_iter()
+            .filter(|bill| current_epoch.saturating_sub(bill.minted_at) >= min_age)
+            .cloned()
+            .collect()
+    }
+
+    /// The circulating bills in ascending serial order
+    pub fn bills_sorted(&self) -> Vec<&Bill> {
+        let mut bills: Vec<&Bill> = self.bills.iter().collect();
+        bills.sort();
+        bills
+    }
+
+    /// The total value of all the bills owned by the given user, including their shares in
+    /// co-owned bills
+    pub fn balance_of(&self, user: &User) -> u64 {
+        let shares = self.shared_bills.values().fold(0, |total: u64, bill| {
+            total.saturating_add(bill.share_of(user))
+        });
+        self.bills
+            .iter()
+            .filter(|bill| bill.owner == *user)
+            .fold(shares, |total, bill| total.saturating_add(bill.amount))
+    }
+
+    /// Collapse the bills into the total balance of each user who owns any, for tooling that
+    /// thinks in accounts rather than bills. Each balance matches [`State::balance_of`].
+    pub fn to_balance_map(&self) -> BTreeMap<User, u64> {
+        let mut balances = BTreeMap::new();
+        let owned = self.bills.iter().map(|bill| (bill.owner, bill.amount));
+        let shares = self
+            .shared_bills
+            .values()
+            .flat_map(|bill| bill.shares.iter().copied());
+        for (owner, amount) in owned.chain(shares) {
+            let balance: &mut u64 = balances.entry(owner).or_default();
+            *balance = balance.saturating_add(amount);
+        }
+        balances
+    }
+
+    /// The total value of all the bills currently in circulation, including co-owned bills
+    pub fn total_supply(&self) -> u64 {
+        let shared = self
+            .shared_bills
+            .values()
+            .fold(0, |total: u64, bill| total.saturating_add(bill.amount()));
+        self.bills
+            .iter()
+            .fold(shared, |total, bill| total.saturating_add(bill.amount))
+    }
+
+    /// The co-owned bill with the given serial, if it is in circulation
+    pub fn shared_bill(&self, serial: u64) -> Option<&SharedBill> {
+        self.shared_bills.get(&serial)
+    }
+
+    /// The Gini coefficient of the wealth distribution across users. Balances are totalled
+    /// per user as by [`State::balance_of`], and every user is counted, so a user holding
+    /// nothing pulls the coefficient up. Returns `None` when there are no bills in circulation.
+    pub fn gini(&self) -> Option<f64> {
+        if self.bills.is_empty() && self.shared_bills.is_empty() {
+            return None;
+        }
+
+        let balances: Vec<f64> = User::ALL
+            .iter()
+            .map(|user| self.balance_of(user) as f64)
+            .collect();
+        let n = balances.len() as f64;
+        let total: f64 = balances.iter().sum();
+        if total == 0.0 {
+            return Some(0.0);
+        }
+        let mut absolute_differences = 0.0;
+        for a in balances.iter() {
+            for b in balances.iter() {
+                absolute_differences += (a - b).abs();
+            }
+        }
+        Some(absolute_differences / (2.0 * n * total))
+    }
+
+    /// How fragmented a user's holdings are, measured as the number of bills they hold per
+    /// unit of balance. A single bill holding the whole balance scores close to zero, while a
+    /// balance made entirely of bills worth one scores exactly one. Returns `None` if the user
+    /// holds nothing.
+    pub fn fragmentation(&self, user: &User) -> Option<f64> {
+        let bills = self.bills.iter().filter(|bill| bill.owner == *user).count();
+        let balance = self.balance_of(user);
+        if balance == 0 {
+            return None;
+        }
+        Some(bills as f64 / balance as f64)
+    }
+
+    /// A digest committing to everything that decides whether a transition is valid: the
+    /// circulating bills with their metadata, the serial counter, the epoch, the rules and
+    /// authorities in force, and all pooled, escrowed, shared and approved value. Two states
+    /// with the same root accept and reject exactly the same transitions.
+    pub fn state_root(&self) -> u64 {
+        let bills: Vec<_> = self
+            .bills_sorted()
+            .into_iter()
+            .map(|bill| (bill, bill.minted_at, bill.expires_at, &bill.spend_condition))
+            .collect();
+        let mut authorities: Vec<User> = self.authorities.iter().copied().collect();
+        authorities.sort();
+        let rules = (
+            self.paused,
+            self.pause_authority,
+            self.forbid_recombination,
+            self.limit_window_spend,
+            self.strict_receive_order,
+            self.transfer_tax,
+            authorities,
+            self.chain_id,
+            &self.issuance,
+        );
+        let held = (
+            self.burn_pool,
+            sorted_entries(&self.escrows),
+            sorted_entries(&self.mints_this_epoch),
+            sorted_entries(&self.shared_bills),
+            sorted_entries(&self.allowances),
+            sorted_entries(&self.recent_spends),
+        );
+        hash(&(bills, self.next_serial, self.epoch, rules, held))
+    }
+
+    /// The root of a Merkle tree whose leaves are the digests of the circulating bills in
+    /// ascending serial order. A state with no bills has the all-zero root.
+    pub fn merkle_root(&self) -> [u8; 32] {
+        let mut level: Vec<[u8; 32]> = self.bills_sorted().into_iter().map(digest32).collect();
+        if level.is_empty() {
+            return [0; 32];
+        }
+        while level.len() > 1 {
+            level = merkle_parents(&level);
+        }
+        level[0]
+    }
+
+    /// A proof that the circulating bill with the given serial is part of the
+    /// [`merkle_root`](State::merkle_root), or `None` if no such bill is in circulation
+    pub fn prove_inclusion(&self, serial: u64) -> Option<MerkleProof> {
+        let bills = self.bills_sorted();
+        let mut index = bills.iter().position(|bill| bill.serial == serial)?;
+        let mut level: Vec<[u8; 32]> = bills.into_iter().map(digest32).collect();
+        let mut siblings = Vec::new();
+        while level.len() > 1 {
+            let sibling = if index % 2 == 0 {
+                (level[(index + 1).min(level.len() - 1)], false)
+            } else {
+                (level[index - 1], true)
+            };
+            siblings.push(sibling);
+            level = merkle_parents(&level);
+            index /= 2;
+        }
+        Some(MerkleProof { siblings })
+    }
+
+    /// Encode the state in a simple length-prefixed binary format. All integers are
+    /// little-endian.
+    ///
+    /// - the number of bills as a `u64`
+    /// - for each bill in ascending serial order, the owner tag as a `u8` (Alice = 0,
+    ///   Bob = 1, Charlie = 2), followed by the amount and the serial as `u64`s
+    /// - the next serial as a `u64`
+    ///
+    /// Only the circulating bills' owners, amounts and serials and the next serial are
+    /// encoded. Nothing else about the state is part of the encoding.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let bills = self.bills_sorted();
+
+        let mut bytes = Vec::with_capacity(16 + bills.len() * 17);
+        bytes.extend_from_slice(&(bills.len() as u64).to_le_bytes());
+        for bill in bills {
+            let tag: u8 = match bill.owner {
+                User::Alice => 0,
+                User::Bob => 1,
+                User::Charlie => 2,
+            };
+            bytes.push(tag);
+            bytes.extend_from_slice(&bill.amount.to_le_bytes());
+            bytes.extend_from_slice(&bill.serial.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.next_serial.to_le_bytes());
+        bytes
+    }
+
+    /// Decode a state previously encoded with [`State::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<State, DecodeError> {
+        let mut input = bytes;
+        let count = read_u64(&mut input)?;
+
+        let mut bills = HashSet::new();
+        for _ in 0..count {
+            let (&tag, rest) = input.split_first().ok_or(DecodeError::UnexpectedEnd)?;
+            input = rest;
+            let owner = match tag {
+                0 => User::Alice,
+                1 => User::Bob,
+                2 => User::Charlie,
+                _ => return Err(DecodeError::UnknownOwner(tag)),
+            };
+            let amount = read_u64(&mut input)?;
+            let serial = read_u64(&mut input)?;
+            bills.insert(Bill::new(owner, amount, serial));
+        }
+        let next_serial = read_u64(&mut input)?;
+
+        if !input.is_empty() {
+            return Err(DecodeError::TrailingBytes);
+        }
+        let mut state = State::new();
+        state.bills = bills;
+        state.next_serial = next_serial;
+        Ok(state)
+    }
+
+    /// Partition the circulating bills into `n` states, placing each bill in the shard at
+    /// index `serial % n`. Every shard keeps the original next serial. Asking for zero shards
+    /// returns no shards at all.
+    pub fn shard(&self, n: usize) -> Vec<State> {
+        let mut shards: Vec<State> = (0..n)
+            .map(|_| {
+                let mut shard = State::new_with_serial_base(self.serial_base);
+                shard.next_serial = self.next_serial;
+                shard
+            })
+            .collect();
+        if n == 0 {
+            return shards;
+        }
+        for bill in self.bills.iter() {
+            shards[(bill.serial % n as u64) as usize]
+                .bills
+                .insert(bill.clone());
+        }
+        shards
+    }
+
+    /// The serials of the bills that were spent to create the circulating bill with the
+    /// given serial. Minted bills and serials that are not in circulation have no parents.
+    pub fn provenance(&self, serial: u64) -> Vec<u64> {
+        self.bills
+            .iter()
+            .find(|bill| bill.serial == serial)
+            .map(|bill| bill.parents.clone())
+            .unwrap_or_default()
+    }
+
+    /// Render the provenance of the circulating bills as a Graphviz DOT graph. Each bill is a
+    /// node labelled with its owner, amount and serial, and each edge points from a parent
+    /// serial to the bill it funded. Parents that have already been spent are drawn dashed.
+    pub fn to_dot(&self) -> String {
+        let bills = self.bills_sorted();
+        let circulating: HashSet<u64> = bills.iter().map(|bill| bill.serial).collect();
+        let mut spent: Vec<u64> = bills
+            .iter()
+            .flat_map(|bill| bill.parents.iter().copied())
+            .filter(|serial| !circulating.contains(serial))
+            .collect();
+        spent.sort();
+        spent.dedup();
+
+        let mut dot = String::from("digraph provenance {\n");
+        for bill in bills.iter() {
+            dot.push_str(&format!(
+                "    bill{} [label=\"{:?} {} #{}\"];\n",
+                bill.serial, bill.owner, bill.amount, bill.serial
+            ));
+        }
+        for serial in spent {
+            dot.push_str(&format!(
+                "    bill{serial} [label=\"#{serial}\", style=dashed];\n"
+            ));
+        }
+        for bill in bills.iter() {
+            for parent in bill.parents.iter() {
+                dot.push_str(&format!("    bill{} -> bill{};\n", parent, bill.serial));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// A digital cash state that keeps its bills in a sorted set rather than a hash set. It
+/// holds exactly the same information as a [`State`] but always iterates its bills in
+/// serial order.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OrderedState {
+    /// The set of currently circulating bills, ordered by serial
+    bills: BTreeSet<Bill>,
+    /// Everything else about the state. Its own bill set is always empty.
+    rest: State,
+}
+
+impl OrderedState {
+    pub fn next_serial(&self) -> u64 {
+        self.rest.next_serial
+    }
+
+    /// The circulating bills in ascending serial order
+    pub fn iter(&self) -> impl Iterator<Item = &Bill> {
+        self.bills.iter()
+    }
+}
+
+impl From<State> for OrderedState {
+    fn from(mut state: State) -> Self {
+        OrderedState {
+            bills: state.bills.drain().collect(),
+            rest: state,
+        }
+    }
+}
+
+impl From<OrderedState> for State {
+    fn from(state: OrderedState) -> Self {
+        let mut rest = state.rest;
+        rest.bills = state.bills.into_iter().collect();
+        rest
+    }
+}
+
+/// The reasons a byte string can fail to decode as a [`State`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DecodeError {
+    /// The input ended before the state was fully decoded
+    UnexpectedEnd,
+    /// A bill's owner tag does not correspond to any user
+    UnknownOwner(u8),
+    /// There is input left over after the state was fully decoded
+    TrailingBytes,
+}
+
+/// Read a little-endian `u64` from the front of the input, advancing past it
+fn read_u64(input: &mut &[u8]) -> Result<u64, DecodeError> {
+    if input.len() < 8 {
+        return Err(DecodeError::UnexpectedEnd);
+    }
+    let (number, rest) = input.split_at(8);
+    *input = rest;
+    Ok(u64::from_le_bytes(number.try_into().unwrap()))
+}
+
+impl FromIterator<Bill> for State {
+    fn from_iter<I: IntoIterator<Item = Bill>>(iter: I) -> Self {
+        let mut state = State::new();
+
+        for i in iter {
+            state
+                .add_bill(i)
+                .expect("a fresh state has a serial for every bill it can hold");
+        }
+        state
+    }
+}
+
+impl<const N: usize> From<[Bill; N]> for State {
+    fn from(value: [Bill; N]) -> Self {
+        State::from_iter(value)
+    }
+}
+
+/// The state transitions that users can make in a digital cash system
+#[derive(Hash)]
+pub enum CashTransaction {
+    /// Mint a single new bill owned by the minter
+    Mint { minter: User, amount: u64 },
+    /// Mint a single new bill and hand it straight to the recipient, in one atomic step. The
+    /// mint counts against the minter's limits just like a [`Mint`](CashTransaction::Mint).
+    MintAndSend {
+        minter: User,
+        recipient: User,
+        amount: u64,
+    },
+    /// Mint one new bill for each of the recipients in the given amount. Serials are
+    /// assigned contiguously in the order the recipients are listed. Zero amounts are
+    /// skipped and do not consume a serial.
+    Airdrop {
+        signer: User,
+        recipients: Vec<(User, u64)>,
+    },
+    /// Send some money from some users to other users. The money does not all need
+    /// to come from the same user, and it does not all need to go to the same user.
+    /// The total amount received must be less than or equal to the amount spent.
+    /// The discrepancy between the amount sent and received is destroyed. At least one
+    /// bill must be received; destroying bills outright is done with a `Burn`.
+    ///
+    /// A transfer may carry a memo of up to [`MAX_MEMO_LEN`] bytes as a payment reference.
+    /// The memo has no effect on the accounting.
+    Transfer {
+        spends: Vec<Bill>,
+        receives: Vec<Bill>,
+        memo: Vec<u8>,
+    },
+    /// A transfer signed by the given users, which allows it to spend bills carrying a spend
+    /// condition that the signers satisfy. The signatures cover the chain id, so the transfer
+    /// only applies on the chain it was signed for.
+    SignedTransfer {
+        chain_id: u32,
+        signers: Vec<User>,
+        spends: Vec<Bill>,
+        receives: Vec<Bill>,
+    },
+    /// A transfer whose surplus, the difference between the amount spent and the amount
+    /// received, is captured in the burn pool instead of being destroyed
+    PooledTransfer {
+        spends: Vec<Bill>,
+        receives: Vec<Bill>,
+    },
+    /// Pay the given amount out of the burn pool as a new bill owned by the recipient
+    Refund { recipient: User, amount: u64 },
+    /// Lock a bill in escrow. The claimant can take it by revealing a secret whose hash, as
+    /// computed by [`hash32`], matches the given one.
+    Escrow {
+        bill: Bill,
+        claimant: User,
+        secret_hash: [u8; 32],
+    },
+    /// Release an escrowed bill to its claimant by revealing the secret. The claimant
+    /// receives a new bill of the same amount.
+    Claim { serial: u64, secret: Vec<u8> },
+    /// Return an escrowed bill to its original owner once it has been locked for
+    /// [`ESCROW_TIMEOUT`] epochs. The owner receives a new bill of the same amount.
+    ReclaimEscrow { serial: u64 },
+    /// Pool the given bills into a single new bill co-owned by the listed users, each holding
+    /// the given number of shares. The shares may add up to less than the value spent, in
+    /// which case the difference is destroyed, just as with a transfer. No co-owner may hold
+    /// more than [`MAX_BILL_AMOUNT`], since each share can be exited into a bill of its own.
+    ShareBills {
+        spends: Vec<Bill>,
+        shares: Vec<(User, u64)>,
+    },
+    /// Move a co-owner's shares out of a co-owned bill into a new bill that they own alone.
+    /// The co-owned bill is removed once its last co-owner has exited.
+    ExitShare { serial: u64, shareholder: User },
+    /// Destroy the given bills entirely. The burned value is attributed to the signer.
+    Burn { signer: User, spends: Vec<Bill> },
+    /// Destroy the given bills except for the change, which is created as a new bill just as
+    /// a transfer would. The burned value is attributed to the signer.
+    PartialBurn {
+        signer: User,
+        spends: Vec<Bill>,
+        change: Bill,
+    },
+    /// Advance to the next epoch. Bills that expire at the new epoch are destroyed, and any
+    /// scheduled issuance is minted.
+    Tick,
+    /// Do nothing. Always valid, even while the system is paused, and never changes the
+    /// state. Useful as a placeholder in a batch.
+    Nop,
+    /// Approve the spender to spend up to the given amount of the owner's bills in signed
+    /// transfers, replacing any earlier approval. An approval of zero revokes it.
+    Approve {
+        owner: User,
+        spender: User,
+        amount: u64,
+    },
+    /// Pause or unpause the system. Only the pause authority may do this. While the system
+    /// is paused, the only transitions allowed are this one and ticks.
+    SetPaused { value: bool, authority: User },
+    /// Burn the user's bills, smallest first, until they hold no more than the cap in bills.
+    /// Whole bills are burned, so the balance may end up below the cap. Shares in co-owned
+    /// bills cannot be burned this way, so they neither count towards the cap nor are
+    /// touched. Only the pause authority may do this.
+    CapBalance {
+        user: User,
+        cap: u64,
+        authority: User,
+    },
+    /// Charge a holding tax on every circulating bill. Each bill loses `rate_per_epoch`
+    /// [`BASIS_POINTS`] of its amount for every epoch it has been held since it was created or
+    /// last charged, whichever is later. The lost value is burned, and bills that decay
+    /// entirely are removed. Only the pause authority may do this.
+    ApplyDemurrage {
+        rate_per_epoch: u64,
+        authority: User,
+    },
+    /// Hand everything `old` holds over to `new`: the bills they own, keeping each serial and
+    /// amount, along with their place in spend conditions, escrows, co-owned bills,
+    /// allowances and the spend window. Only `old` may sign this, and `new` must not hold any
+    /// value yet, or the rotation would be a payment that dodges the transfer rules.
+    RotateKey { old: User, new: User, signer: User },
+    /// Apply the inner transaction only if the condition holds in the starting state
+    ConditionalTransfer {
+        condition: Condition,
+        inner: Box<CashTransaction>,
+    },
+}
+
+/// A plain transfer with everything that a known state can fill back in left out. Inputs
+/// are given only by serial, since the state knows their owners and amounts, and outputs
+/// only by owner and amount, since they take the next serials in order.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CompactTransfer {
+    pub inputs: Vec<u64>,
+    pub outputs: Vec<(User, u64)>,
+    pub memo: Vec<u8>,
+}
+
+impl CashTransaction {
+    /// The compact form of a plain transfer, or `None` for any other transaction
+    pub fn to_compact(&self) -> Option<CompactTransfer> {
+        let CashTransaction::Transfer {
+            spends,
+            receives,
+            memo,
+        } = self
+        else {
+            return None;
+        };
+        Some(CompactTransfer {
+            inputs: spends.iter().map(|bill| bill.serial).collect(),
+            outputs: receives
+                .iter()
+                .map(|bill| (bill.owner, bill.amount))
+                .collect(),
+            memo: memo.clone(),
+        })
+    }
+
+    /// Expand a compact transfer against the state it is meant to be applied to, or `None`
+    /// if any of its inputs is not in circulation there
+    pub fn from_compact(state: &State, c: &CompactTransfer) -> Option<CashTransaction> {
+        let spends = c
+            .inputs
+            .iter()
+            .map(|serial| {
+                state
+                    .bills
+                    .iter()
+                    .find(|bill| bill.serial == *serial)
+                    .map(|bill| Bill::new(bill.owner, bill.amount, bill.serial))
+            })
+            .collect::<Option<Vec<Bill>>>()?;
+        let receives = c
+            .outputs
+            .iter()
+            .zip(state.next_serial..)
+            .map(|((owner, amount), serial)| Bill::new(*owner, *amount, serial))
+            .collect();
+        Some(CashTransaction::Transfer {
+            spends,
+            receives,
+            memo: c.memo.clone(),
+        })
+    }
+
+    /// The name of this kind of transaction
+    pub fn name(&self) -> &'static str {
+        match self {
+            CashTransaction::Mint { .. } => "Mint",
+            CashTransaction::MintAndSend { .. } => "MintAndSend",
+            CashTransaction::Airdrop { .. } => "Airdrop",
+            CashTransaction::Transfer { .. } => "Transfer",
+            CashTransaction::SignedTransfer { .. } => "SignedTransfer",
+            CashTransaction::PooledTransfer { .. } => "PooledTransfer",
+            CashTransaction::Refund { .. } => "Refund",
+            CashTransaction::Escrow { .. } => "Escrow",
+            CashTransaction::Claim { .. } => "Claim",
+            CashTransaction::ReclaimEscrow { .. } => "ReclaimEscrow",
+            CashTransaction::ShareBills { .. } => "ShareBills",
+            CashTransaction::ExitShare { .. } => "ExitShare",
+            CashTransaction::Burn { .. } => "Burn",
+            CashTransaction::PartialBurn { .. } => "PartialBurn",
+            CashTransaction::Tick => "Tick",
+            CashTransaction::Nop => "Nop",
+            CashTransaction::Approve { .. } => "Approve",
+            CashTransaction::SetPaused { .. } => "SetPaused",
+            CashTransaction::CapBalance { .. } => "CapBalance",
+            CashTransaction::ApplyDemurrage { .. } => "ApplyDemurrage",
+            CashTransaction::RotateKey { .. } => "RotateKey",
+            CashTransaction::ConditionalTransfer { .. } => "ConditionalTransfer",
+        }
+    }
+
+    /// A JSON Schema document describing the shape of a transaction, so that external tooling
+    /// can validate transactions before submitting them. Each variant is an object with a
+    /// single key naming it, except for unit variants, which are the bare name as a string.
+    pub fn json_schema() -> String {
+        const U64: &str = r#"{"type":"integer","minimum":0}"#;
+        const USER: &str = r##"{"$ref":"#/definitions/User"}"##;
+        const BILL: &str = r##"{"$ref":"#/definitions/Bill"}"##;
+        const BILLS: &str = r##"{"type":"array","items":{"$ref":"#/definitions/Bill"}}"##;
+        const USERS: &str = r##"{"type":"array","items":{"$ref":"#/definitions/User"}}"##;
+        const BYTES: &str =
+            r#"{"type":"array","items":{"type":"integer","minimum":0,"maximum":255}}"#;
+        const MEMO: &str = r#"{"type":"array","items":{"type":"integer","minimum":0,"maximum":255},"maxItems":32}"#;
+        const HASH: &str = r#"{"type":"array","items":{"type":"integer","minimum":0,"maximum":255},"minItems":32,"maxItems":32}"#;
+        const RECIPIENTS: &str = r##"{"type":"array","items":{"type":"array","items":[{"$ref":"#/definitions/User"},{"type":"integer","minimum":0}],"minItems":2,"maxItems":2}}"##;
+        const SHARES: &str = RECIPIENTS;
+        const CONDITION: &str = r##"{"$ref":"#/definitions/Condition"}"##;
+        const TX: &str = r##"{"$ref":"#"}"##;
+
+        let variants: &[(&str, &[(&str, &str)])] = &[
+            ("Mint", &[("minter", USER), ("amount", U64)]),
+            (
+                "MintAndSend",
+                &[("minter", USER), ("recipient", USER), ("amount", U64)],
+            ),
+            ("Airdrop", &[("signer", USER), ("recipients", RECIPIENTS)]),
+            (
+                "Transfer",
+                &[("spends", BILLS), ("receives", BILLS), ("memo", MEMO)],
+            ),
+            (
+                "SignedTransfer",
+                &[
+                    ("chain_id", U64),
+                    ("signers", USERS),
+                    ("spends", BILLS),
+                    ("receives", BILLS),
+                ],
+            ),
+            ("PooledTransfer", &[("spends", BILLS), ("receives", BILLS)]),
+            ("Refund", &[("recipient", USER), ("amount", U64)]),
+            (
+                "Escrow",
+                &[("bill", BILL), ("claimant", USER), ("secret_hash", HASH)],
+            ),
+            ("Claim", &[("serial", U64), ("secret", BYTES)]),
+            ("ReclaimEscrow", &[("serial", U64)]),
+            ("ShareBills", &[("spends", BILLS), ("shares", SHARES)]),
+            ("ExitShare", &[("serial", U64), ("shareholder", USER)]),
+            ("Burn", &[("signer", USER), ("spends", BILLS)]),
+            (
+                "PartialBurn",
+                &[("signer", USER), ("spends", BILLS), ("change", BILL)],
+            ),
+            ("Tick", &[]),
+            ("Nop", &[]),
+            (
+                "Approve",
+                &[("owner", USER), ("spender", USER), ("amount", U64)],
+            ),
+            (
+                "SetPaused",
+                &[("value", r#"{"type":"boolean"}"#), ("authority", USER)],
+            ),
+            (
+                "CapBalance",
+                &[("user", USER), ("cap", U64), ("authority", USER)],
+            ),
+            (
+                "ApplyDemurrage",
+                &[("rate_per_epoch", U64), ("authority", USER)],
+            ),
+            (
+                "RotateKey",
+                &[("old", USER), ("new", USER), ("signer", USER)],
+            ),
+            (
+                "ConditionalTransfer",
+                &[("condition", CONDITION), ("inner", TX)],
+            ),
+        ];
+
+        let one_of = variants
+            .iter()
+            .map(|(name, fields)| {
+                if fields.is_empty() {
+                    return format!(r#"{{"const":"{name}"}}"#);
+                }
+                let properties = fields
+                    .iter()
+                    .map(|(field, schema)| format!(r#""{field}":{schema}"#))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                let required = fields
+                    .iter()
+                    .map(|(field, _)| format!(r#""{field}""#))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                format!(
+                    r#"{{"type":"object","properties":{{"{name}":{{"type":"object","properties":{{{properties}}},"required":[{required}],"additionalProperties":false}}}},"required":["{name}"],"additionalProperties":false}}"#
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let definitions = format!(
+            concat!(
+                r#"{{"User":{{"enum":["Alice","Bob","Charlie"]}},"#,
+                r#""Bill":{{"type":"object","properties":{{"owner":{user},"amount":{u64},"serial":{u64}}},"required":["owner","amount","serial"]}},"#,
+                r#""Condition":{{"oneOf":[{{"type":"object","properties":{{"MinBalance":{{"type":"array","items":[{user},{u64}],"minItems":2,"maxItems":2}}}},"required":["MinBalance"]}},{{"type":"object","properties":{{"BillExists":{u64}}},"required":["BillExists"]}}]}}}}"#,
+            ),
+            user = USER,
+            u64 = U64,
+        );
+
+        format!(
+            r#"{{"$schema":"http://json-schema.org/draft-07/schema#","title":"CashTransaction","oneOf":[{one_of}],"definitions":{definitions}}}"#
+        )
+    }
+}
+
+/// A predicate on the state that can gate a conditional transfer
+#[derive(Hash)]
+pub enum Condition {
+    /// The user's balance is at least the given amount
+    MinBalance(User, u64),
+    /// A bill with the given serial is in circulation
+    BillExists(u64),
+}
+
+impl Condition {
+    /// Evaluate this condition against the given state
+    pub fn holds(&self, state: &State) -> bool {
+        match self {
+            Condition::MinBalance(user, amount) => state.balance_of(user) >= *amount,
+            Condition::BillExists(serial) => state.bills.iter().any(|bill| bill.serial == *serial),
+        }
+    }
+}
+
+/// Notable things that happen when a transition is applied
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CashEvent {
+    /// Value was destroyed by an explicit burn signed by the given user
+    ValueBurned { signer: User, amount: u64 },
+    /// A bill reached its expiry epoch and was destroyed
+    BillExpired { serial: u64, amount: u64 },
+    /// A bill lost the given amount to demurrage. It was destroyed if nothing was left.
+    BillDecayed { serial: u64, amount: u64 },
+}
+
+/// The reasons a transition can be rejected by the digital cash system. A rejected
+/// transition leaves the state unchanged.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TransferError {
+    /// Minting the requested amounts would overflow the total supply
+    SupplyOverflow,
+    /// A transfer must spend at least one bill
+    NoSpends,
+    /// No transaction may create a bill worth nothing
+    ZeroAmountOutput,
+    /// A new bill would be worth more than [`MAX_BILL_AMOUNT`]
+    AmountTooLarge,
+    /// The same bill appears in both the spends and the receives
+    SpendAlsoReceived,
+    /// The total amount received overflows
+    OutputOverflow,
+    /// The total amount spent overflows
+    InputOverflow,
+    /// A spent bill is not in circulation
+    NonexistentBill { serial: u64 },
+    /// The same bill is spent more than once
+    DuplicateSpend { serial: u64 },
+    /// A received bill reuses the serial of a spent bill
+    SerialAlreadySpent { serial: u64 },
+    /// A received bill does not carry the next serial in sequence
+    NonContiguousSerial { expected: u64, got: u64 },
+    /// There are not enough serials left to number all the received bills
+    SerialSpaceExhausted,
+    /// More value is received than is spent
+    OutputsExceedInputs { spent: u64, received: u64 },
+    /// A received bill is worth more than the largest spent bill, while recombination is
+    /// forbidden
+    OutputExceedsInput { largest_spent: u64, received: u64 },
+    /// The condition of a conditional transfer does not hold
+    ConditionNotMet,
+    /// A transfer must receive at least one bill. Use a burn to destroy bills.
+    NoReceives,
+    /// A transaction was made for a different chain
+    WrongChain,
+    /// A transfer creates more than [`FANOUT_FACTOR`] bills for every bill it spends
+    ExcessiveFanout,
+    /// The receives are not listed in ascending serial order, while strict ordering is on
+    UnorderedReceives,
+    /// A transfer does not leave enough unreceived to pay the tax, while the tax is on
+    InsufficientTax { required: u64, paid: u64 },
+    /// A spent bill has expired
+    ExpiredBill { serial: u64 },
+    /// The system is paused
+    SystemPaused,
+    /// The signer is not allowed to make this transition
+    Unauthorized,
+    /// A delegated spend uses more of the owner's value than the spender was approved for
+    AllowanceExceeded { available: u64, requested: u64 },
+    /// A user would send away more than [`MAX_WINDOW_SPEND`] within the last
+    /// [`SPEND_WINDOW`] epochs
+    WindowSpendExceeded { available: u64, requested: u64 },
+    /// A refund asks for more than the burn pool holds
+    InsufficientPool { available: u64, requested: u64 },
+    /// No bill with the given serial is in escrow
+    EscrowNotFound { serial: u64 },
+    /// The revealed secret does not match the escrow's hash
+    WrongSecret,
+    /// The escrow has not been locked long enough to be reclaimed
+    EscrowNotExpired,
+    /// The minter has already made [`MAX_MINTS_PER_EPOCH`] mints this epoch
+    MintRateExceeded,
+    /// A transfer memo is longer than [`MAX_MEMO_LEN`] bytes
+    MemoTooLong,
+    /// The same user is listed more than once as a co-owner of a bill
+    DuplicateShareholder,
+    /// The user holds no shares in the co-owned bill
+    NotAShareholder,
+    /// The new key of a rotation already holds value
+    KeyInUse,
+}
+
+impl TransferError {
+    /// The name of this kind of error, without any of its details
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransferError::SupplyOverflow => "SupplyOverflow",
+            TransferError::NoSpends => "NoSpends",
+            TransferError::ZeroAmountOutput => "ZeroAmountOutput",
+            TransferError::AmountTooLarge => "AmountTooLarge",
+            TransferError::SpendAlsoReceived => "SpendAlsoReceived",
+            TransferError::OutputOverflow => "OutputOverflow",
+            TransferError::InputOverflow => "InputOverflow",
+            TransferError::NonexistentBill { .. } => "NonexistentBill",
+            TransferError::DuplicateSpend { .. } => "DuplicateSpend",
+            TransferError::SerialAlreadySpent { .. } => "SerialAlreadySpent",
+            TransferError::NonContiguousSerial { .. } => "NonContiguousSerial",
+            TransferError::SerialSpaceExhausted => "SerialSpaceExhausted",
+            TransferError::OutputsExceedInputs { .. } => "OutputsExceedInputs",
+            TransferError::OutputExceedsInput { .. } => "OutputExceedsInput",
+            TransferError::ConditionNotMet => "ConditionNotMet",
+            TransferError::NoReceives => "NoReceives",
+            TransferError::WrongChain => "WrongChain",
+            TransferError::ExcessiveFanout => "ExcessiveFanout",
+            TransferError::UnorderedReceives => "UnorderedReceives",
+            TransferError::InsufficientTax { .. } => "InsufficientTax",
+            TransferError::ExpiredBill { .. } => "ExpiredBill",
+            TransferError::SystemPaused => "SystemPaused",
+            TransferError::Unauthorized => "Unauthorized",
+            TransferError::AllowanceExceeded { .. } => "AllowanceExceeded",
+            TransferError::WindowSpendExceeded { .. } => "WindowSpendExceeded",
+            TransferError::InsufficientPool { .. } => "InsufficientPool",
+            TransferError::EscrowNotFound { .. } => "EscrowNotFound",
+            TransferError::WrongSecret => "WrongSecret",
+            TransferError::EscrowNotExpired => "EscrowNotExpired",
+            TransferError::MintRateExceeded => "MintRateExceeded",
+            TransferError::MemoTooLong => "MemoTooLong",
+            TransferError::DuplicateShareholder => "DuplicateShareholder",
+            TransferError::NotAShareholder => "NotAShareholder",
+            TransferError::KeyInUse => "KeyInUse",
+        }
+    }
+}
+
+/// How to treat the amounts of a transfer adding up to more than a `u64` can hold
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OverflowMode {
+    /// Reject the transfer
+    Checked,
+    /// Clamp the total to `u64::MAX` and carry on
+    Saturating,
+}
+
+impl OverflowMode {
+    /// Add two amounts, or `None` if the sum overflows and this mode rejects overflow
+    fn add(self, a: u64, b: u64) -> Option<u64> {
+        match self {
+            OverflowMode::Checked => a.checked_add(b),
+            OverflowMode::Saturating => Some(a.saturating_add(b)),
+        }
+    }
+}
+
+impl DigitalCashSystem {
+    /// Calculate the resulting state when this state undergoes the given transition, or the
+    /// reason that the transition is not valid from this state.
+    pub fn try_next_state(
+        starting_state: &State,
+        t: &CashTransaction,
+    ) -> Result<State, TransferError> {
+        DigitalCashSystem::apply_with_events(starting_state, t).map(|(state, _)| state)
+    }
+
+    /// Like [`StateMachine::next_state`], but reports every transition and its outcome to
+    /// the given observer.
+    pub fn next_state_observed<O: CashObserver>(
+        starting_state: &State,
+        t: &CashTransaction,
+        observer: &mut O,
+    ) -> State {
+        let result = DigitalCashSystem::try_next_state(starting_state, t);
+        observer.on_transition(starting_state, t, &result);
+        result.unwrap_or_else(|_| starting_state.clone())
+    }
+
+    /// Like [`DigitalCashSystem::try_next_state`], but also reports the events emitted while
+    /// applying the transition.
+    pub fn apply_with_events(
+        starting_state: &State,
+        t: &CashTransaction,
+    ) -> Result<(State, Vec<CashEvent>), TransferError> {
+        DigitalCashSystem::apply_with_mode(starting_state, t, OverflowMode::Checked)
+    }
+
+    /// Like [`DigitalCashSystem::apply_with_events`], but with the given behaviour when the
+    /// amounts spent or received by a transfer add up to more than a `u64` can hold.
+    pub fn apply_with_mode(
+        starting_state: &State,
+        t: &CashTransaction,
+        mode: OverflowMode,
+    ) -> Result<(State, Vec<CashEvent>), TransferError> {
+        let mut next_state = starting_state.clone();
+        let mut events = Vec::new();
+
+        if starting_state.paused
+            && !matches!(
+                t,
+                CashTransaction::SetPaused { .. } | CashTransaction::Tick | CashTransaction::Nop
+            )
+        {
+            return Err(TransferError::SystemPaused);
+        }
+
+        match t {
+            CashTransaction::Mint { minter, amount }
+            | CashTransaction::MintAndSend { minter, amount, .. } => {
+                starting_state.check_authority(minter)?;
+                if *amount == 0 {
+                    return Err(TransferError::ZeroAmountOutput);
+                }
+                if *amount > MAX_BILL_AMOUNT {
+                    return Err(TransferError::AmountTooLarge);
+                }
+                let mints = next_state.mints_this_epoch.entry(*minter).or_insert(0);
+                if *mints >= MAX_MINTS_PER_EPOCH {
+                    return Err(TransferError::MintRateExceeded);
+                }
+                *mints += 1;
+                let owner = match t {
+                    CashTransaction::MintAndSend { recipient, .. } => recipient,
+                    _ => minter,
+                };
+                let bill = Bill::new(*owner, *amount, starting_state.next_serial);
+                next_state.add_bill(bill)?;
+            }
+            CashTransaction::Airdrop { signer, recipients } => {
+                starting_state.check_authority(signer)?;
+                // if the airdrop would overflow the total supply, state stays the same
+                let mut total_supply = starting_state.total_supply();
+                for (_, amount) in recipients.iter() {
+                    if *amount > MAX_BILL_AMOUNT {
+                        return Err(TransferError::AmountTooLarge);
+                    }
+                    total_supply = total_supply
+                        .checked_add(*amount)
+                        .ok_or(TransferError::SupplyOverflow)?;
+                }
+                for (recipient, amount) in recipients.iter() {
+                    if *amount == 0 {
+                        continue;
+                    }
+                    let bill = Bill::new(*recipient, *amount, next_state.next_serial);
+                    next_state.add_bill(bill)?;
+                }
+            }
+            CashTransaction::Transfer {
+                spends,
+                receives,
+                memo,
+            } => {
+                if memo.len() > MAX_MEMO_LEN {
+                    return Err(TransferError::MemoTooLong);
+                }
+                check_spend_conditions(&next_state, spends, &[])?;
+                apply_transfer(&mut next_state, spends, receives, mode)?;
+            }
+            CashTransaction::SignedTransfer {
+                chain_id,
+                signers,
+                spends,
+                receives,
+            } => {
+                if *chain_id != starting_state.chain_id {
+                    return Err(TransferError::WrongChain);
+                }
+                let delegated = check_delegated_spends(&next_state, spends, signers)?;
+                apply_transfer(&mut next_state, spends, receives, mode)?;
+                for (key, used) in delegated {
+                    let allowance = next_state.allowances.entry(key).or_default();
+                    *allowance -= used;
+                    if *allowance == 0 {
+                        next_state.allowances.remove(&key);
+                    }
+                }
+            }
+            CashTransaction::PooledTransfer { spends, receives } => {
+                check_spend_conditions(&next_state, spends, &[])?;
+                let surplus = apply_transfer(&mut next_state, spends, receives, mode)?;
+                next_state.burn_pool = next_state.burn_pool.saturating_add(surplus);
+            }
+            CashTransaction::Refund { recipient, amount } => {
+                if *amount == 0 {
+                    return Err(TransferError::ZeroAmountOutput);
+                }
+                if *amount > MAX_BILL_AMOUNT {
+                    return Err(TransferError::AmountTooLarge);
+                }
+                if *amount > next_state.burn_pool {
+                    return Err(TransferError::InsufficientPool {
+                        available: next_state.burn_pool,
+                        requested: *amount,
+                    });
+                }
+                next_state.burn_pool -= amount;
+                let bill = Bill::new(*recipient, *amount, next_state.next_serial);
+                next_state.add_bill(bill)?;
+            }
+            CashTransaction::Escrow {
+                bill,
+                claimant,
+                secret_hash,
+            } => {
+                let spends = std::slice::from_ref(bill);
+                check_spend_conditions(&next_state, spends, &[])?;
+                total_of_existing(&next_state, spends, mode)?;
+                let bill = next_state
+                    .bills
+                    .take(bill)
+                    .expect("existence was just checked");
+                let escrow = Escrow {
+                    bill,
+                    claimant: *claimant,
+                    secret_hash: *secret_hash,
+                    locked_at: next_state.epoch,
+                };
+                next_state.escrows.insert(escrow.bill.serial, escrow);
+            }
+            CashTransaction::Claim { serial, secret } => {
+                let escrow = next_state
+                    .escrows
+                    .remove(serial)
+                    .ok_or(TransferError::EscrowNotFound { serial: *serial })?;
+                if hash32(secret) != escrow.secret_hash {
+                    return Err(TransferError::WrongSecret);
+                }
+                let claimant = escrow.claimant;
+                next_state.release_escrow(escrow, claimant)?;
+            }
+            CashTransaction::ReclaimEscrow { serial } => {
+                let escrow = next_state
+                    .escrows
+                    .remove(serial)
+                    .ok_or(TransferError::EscrowNotFound { serial: *serial })?;
+                if next_state.epoch < escrow.locked_at.saturating_add(ESCROW_TIMEOUT) {
+                    return Err(TransferError::EscrowNotExpired);
+                }
+                let owner = escrow.bill.owner;
+                next_state.release_escrow(escrow, owner)?;
+            }
+            CashTransaction::ShareBills { spends, shares } => {
+                if spends.is_empty() {
+                    return Err(TransferError::NoSpends);
+                }
+                if shares.is_empty() {
+                    return Err(TransferError::NoReceives);
+                }
+                let mut total_shares: u64 = 0;
+                for (i, (owner, amount)) in shares.iter().enumerate() {
+                    if *amount == 0 {
+                        return Err(TransferError::ZeroAmountOutput);
+                    }
+                    if *amount > MAX_BILL_AMOUNT {
+                        return Err(TransferError::AmountTooLarge);
+                    }
+                    if shares[..i].iter().any(|(other, _)| other == owner) {
+                        return Err(TransferError::DuplicateShareholder);
+                    }
+                    total_shares = mode
+                        .add(total_shares, *amount)
+                        .ok_or(TransferError::OutputOverflow)?;
+                }
+                check_no_duplicate_spends(spends)?;
+                check_spend_conditions(&next_state, spends, &[])?;
+                let spent = total_of_existing(&next_state, spends, mode)?;
+                if total_shares > spent {
+                    return Err(TransferError::OutputsExceedInputs {
+                        spent,
+                        received: total_shares,
+                    });
+                }
+                spends.iter().for_each(|bill| {
+                    next_state.bills.remove(bill);
+                });
+                let serial = next_state.next_serial;
+                next_state.shared_bills.insert(
+                    serial,
+                    SharedBill {
+                        shares: shares.clone(),
+                        serial,
+                    },
+                );
+                next_state.increment_serial()?;
+            }
+            CashTransaction::ExitShare {
+                serial,
+                shareholder,
+            } => {
+                let shared = next_state
+                    .shared_bills
+                    .get_mut(serial)
+                    .ok_or(TransferError::NonexistentBill { serial: *serial })?;
+                let amount = shared.share_of(shareholder);
+                if amount == 0 {
+                    return Err(TransferError::NotAShareholder);
+                }
+                if amount > MAX_BILL_AMOUNT {
+                    return Err(TransferError::AmountTooLarge);
+                }
+                shared.shares.retain(|(owner, _)| owner != shareholder);
+                if shared.shares.is_empty() {
+                    next_state.shared_bills.remove(serial);
+                }
+                let bill = Bill {
+                    parents: vec![*serial],
+                    ..Bill::new(*shareholder, amount, next_state.next_serial)
+                };
+                next_state.add_bill(bill)?;
+            }
+            CashTransaction::Burn { signer, spends } => {
+                if spends.is_empty() {
+                    return Err(TransferError::NoSpends);
+                }
+                check_no_duplicate_spends(spends)?;
+                check_spend_conditions(&next_state, spends, &[*signer])?;
+                let amount = total_of_existing(&next_state, spends, mode)?;
+                spends.iter().for_each(|bill| {
+                    next_state.bills.remove(bill);
+                });
+                next_state.total_burned = next_state.total_burned.saturating_add(amount);
+                events.push(CashEvent::ValueBurned {
+                    signer: *signer,
+                    amount,
+                });
+            }
+            CashTransaction::PartialBurn {
+                signer,
+                spends,
+                change,
+            } => {
+                check_spend_conditions(&next_state, spends, &[*signer])?;
+                // any transfer tax is burned already, the rest of the surplus is burned here
+                let burned_before = next_state.total_burned;
+                let surplus =
+                    apply_transfer(&mut next_state, spends, std::slice::from_ref(change), mode)?;
+                next_state.total_burned = next_state.total_burned.saturating_add(surplus);
+                events.push(CashEvent::ValueBurned {
+                    signer: *signer,
+                    amount: next_state.total_burned - burned_before,
+                });
+            }
+            CashTransaction::Nop => {}
+            CashTransaction::Tick => {
+                next_state.epoch = next_state.epoch.saturating_add(1);
+                next_state.mints_this_epoch.clear();
+                next_state.forget_old_spends();
+                let epoch = next_state.epoch;
+                let mut expired: Vec<Bill> = next_state
+                    .bills
+                    .iter()
+                    .filter(|bill| bill.expires_at <= epoch)
+                    .cloned()
+                    .collect();
+                expired.sort();
+                for bill in expired {
+                    next_state.bills.remove(&bill);
+                    next_state.total_burned = next_state.total_burned.saturating_add(bill.amount);
+                    events.push(CashEvent::BillExpired {
+                        serial: bill.serial,
+                        amount: bill.amount,
+                    });
+                }
+                if let Some(schedule) = next_state.issuance.clone() {
+                    next_state
+                        .total_supply()
+                        .checked_add(schedule.per_epoch)
+                        .ok_or(TransferError::SupplyOverflow)?;
+                    let mut remaining = schedule.per_epoch;
+                    while remaining > 0 {
+                        let amount = remaining.min(MAX_BILL_AMOUNT);
+                        let serial = next_state.next_serial;
+                        next_state.add_bill(Bill::new(schedule.treasury, amount, serial))?;
+                        remaining -= amount;
+                    }
+                }
+            }
+            CashTransaction::Approve {
+                owner,
+                spender,
+                amount,
+            } => {
+                if *amount == 0 {
+                    next_state.allowances.remove(&(*owner, *spender));
+                } else {
+                    next_state.allowances.insert((*owner, *spender), *amount);
+                }
+            }
+            CashTransaction::SetPaused { value, authority } => {
+                if starting_state.pause_authority != Some(*authority) {
+                    return Err(TransferError::Unauthorized);
+                }
+                next_state.paused = *value;
+            }
+            CashTransaction::CapBalance {
+                user,
+                cap,
+                authority,
+            } => {
+                if starting_state.pause_authority != Some(*authority) {
+                    return Err(TransferError::Unauthorized);
+                }
+                let mut owned: Vec<Bill> = next_state
+                    .bills
+                    .iter()
+                    .filter(|bill| bill.owner == *user)
+                    .cloned()
+                    .collect();
+                owned.sort_by_key(|bill| (bill.amount, bill.serial));
+                let mut balance = owned
+                    .iter()
+                    .fold(0u64, |total, bill| total.saturating_add(bill.amount));
+                let mut burned: u64 = 0;
+                for bill in owned {
+                    if balance <= *cap {
+                        break;
+                    }
+                    next_state.bills.remove(&bill);
+                    balance -= bill.amount;
+                    burned = burned.saturating_add(bill.amount);
+                }
+                if burned > 0 {
+                    next_state.total_burned = next_state.total_burned.saturating_add(burned);
+                    events.push(CashEvent::ValueBurned {
+                        signer: *authority,
+                        amount: burned,
+                    });
+                }
+            }
+            CashTransaction::ApplyDemurrage {
+                rate_per_epoch,
+                authority,
+            } => {
+                if starting_state.pause_authority != Some(*authority) {
+                    return Err(TransferError::Unauthorized);
+                }
+                let epoch = next_state.epoch;
+                let charged_at = next_state.demurrage_charged_at;
+                let mut bills: Vec<Bill> = next_state.bills.iter().cloned().collect();
+                bills.sort();
+                for bill in bills {
+                    let held = epoch.saturating_sub(bill.minted_at.max(charged_at)) as u128;
+                    // a huge rate or a long holding saturates to decaying the whole bill
+                    let decay = ((bill.amount as u128 * *rate_per_epoch as u128)
+                        .saturating_mul(held)
+                        / BASIS_POINTS as u128)
+                        .min(bill.amount as u128) as u64;
+                    if decay == 0 {
+                        continue;
+                    }
+                    next_state.bills.remove(&bill);
+                    if decay < bill.amount {
+                        next_state.bills.insert(Bill {
+                            amount: bill.amount - decay,
+                            ..bill.clone()
+                        });
+                    }
+                    next_state.total_burned = next_state.total_burned.saturating_add(decay);
+                    events.push(CashEvent::BillDecayed {
+                        serial: bill.serial,
+                        amount: decay,
+                    });
+                }
+                next_state.demurrage_charged_at = epoch;
+            }
+            CashTransaction::RotateKey { old, new, signer } => {
+                if signer != old {
+                    return Err(TransferError::Unauthorized);
+                }
+                if old == new {
+                    return Ok((next_state, events));
+                }
+                let escrowed = next_state
+                    .escrows
+                    .values()
+                    .any(|escrow| escrow.bill.owner == *new || escrow.claimant == *new);
+                if next_state.balance_of(new) > 0 || escrowed {
+                    return Err(TransferError::KeyInUse);
+                }
+                let rekey = |user: User| if user == *old { *new } else { user };
+                next_state.bills = next_state
+                    .bills
+                    .drain()
+                    .map(|bill| bill.rekeyed(old, new))
+                    .collect();
+                for escrow in next_state.escrows.values_mut() {
+                    escrow.bill = escrow.bill.clone().rekeyed(old, new);
+                    escrow.claimant = rekey(escrow.claimant);
+                }
+                for shared in next_state.shared_bills.values_mut() {
+                    for (owner, _) in shared.shares.iter_mut() {
+                        *owner = rekey(*owner);
+                    }
+                }
+                let allowances: Vec<((User, User), u64)> = next_state.allowances.drain().collect();
+                for ((owner, spender), amount) in allowances {
+                    let allowance = next_state
+                        .allowances
+                        .entry((rekey(owner), rekey(spender)))
+                        .or_default();
+                    *allowance = allowance.saturating_add(amount);
+                }
+                if let Some(spends) = next_state.recent_spends.remove(old) {
+                    let window = next_state.recent_spends.entry(*new).or_default();
+                    window.extend(spends);
+                    window.make_contiguous().sort();
+                }
+            }
+            CashTransaction::ConditionalTransfer { condition, inner } => {
+                if !condition.holds(starting_state) {
+                    return Err(TransferError::ConditionNotMet);
+                }
+                return DigitalCashSystem::apply_with_mode(starting_state, inner, mode);
+            }
+        }
+        Ok((next_state, events))
+    }
+}
+
+/// Something that wants to hear about every transition applied to a digital cash system,
+/// such as a metrics collector or a logger.
+pub trait CashObserver {
+    /// Called once per transition with the starting state, the transition and its outcome
+    fn on_transition(
+        &mut self,
+        before: &State,
+        t: &CashTransaction,
+        result: &Result<State, TransferError>,
+    );
+}
+
+/// A transaction that was rejected, as recorded by a [`RejectionLog`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Rejection {
+    /// A short description of the transaction: its kind and the serials it touches
+    pub summary: String,
+    /// Why the transaction was rejected
+    pub error: TransferError,
+    /// The epoch of the state the transaction was rejected from
+    pub timestamp: u64,
+}
+
+/// An observer that records every rejected transaction, so that an operator can find out
+/// why transactions fail most often. Recording is opt in: pass the log to
+/// [`DigitalCashSystem::next_state_observed`] for the transitions that should be logged.
+#[derive(Debug, Default)]
+pub struct RejectionLog {
+    rejections: Vec<Rejection>,
+}
+
+impl RejectionLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every rejection recorded so far, oldest first
+    pub fn rejections(&self) -> &[Rejection] {
+        &self.rejections
+    }
+
+    /// How many rejections were recorded for each kind of error
+    pub fn summary(&self) -> HashMap<&'static str, usize> {
+        let mut summary = HashMap::new();
+        for rejection in self.rejections.iter() {
+            *summary.entry(rejection.error.name()).or_default() += 1;
+        }
+        summary
+    }
+}
+
+impl CashObserver for RejectionLog {
+    fn on_transition(
+        &mut self,
+        before: &State,
+        t: &CashTransaction,
+        result: &Result<State, TransferError>,
+    ) {
+        if let Err(error) = result {
+            self.rejections.push(Rejection {
+                summary: format!("{} touching {:?}", t.name(), touched_serials(t)),
+                error: error.clone(),
+                timestamp: before.epoch,
+            });
+        }
+    }
+}
+
+/// Validate a transfer and apply it to the given state, returning the surplus value that was
+/// spent but not received, less any transfer tax burned out of it. The state is left in an
+/// unspecified condition if the transfer is rejected, so callers should apply it to a copy.
+fn apply_transfer(
+    state: &mut State,
+    spends: &[Bill],
+    receives: &[Bill],
+    mode: OverflowMode,
+) -> Result<u64, TransferError> {
+    // if vec spends is empty, state stays the same
+    if spends.is_empty() {
+        return Err(TransferError::NoSpends);
+    }
+    // bills are only destroyed outright by a signed burn
+    if receives.is_empty() {
+        return Err(TransferError::NoReceives);
+    }
+    // a single transfer may only fragment the ledger so much
+    if receives.len() > spends.len().saturating_mul(FANOUT_FACTOR) {
+        return Err(TransferError::ExcessiveFanout);
+    }
+    // The cheap structural checks run first so that malformed transfers are turned
+    // away before any amounts are looked up or summed.
+
+    // received serials may never run past the range this transfer is allowed to
+    // assign, no matter how the contiguity rule below evolves. That range must itself fit
+    // in the serial space, or computing the expected serials would wrap around.
+    let serial_bound = state
+        .next_serial
+        .checked_add(receives.len() as u64)
+        .ok_or(TransferError::SerialSpaceExhausted)?;
+    for (j, bill) in receives.iter().enumerate() {
+        if bill.serial >= serial_bound {
+            return Err(TransferError::NonContiguousSerial {
+                expected: state.next_serial + j as u64,
+                got: bill.serial,
+            });
+        }
+    }
+    // in strict mode, receives must be listed in their one canonical order
+    if state.strict_receive_order
+        && receives
+            .windows(2)
+            .any(|pair| pair[0].serial >= pair[1].serial)
+    {
+        return Err(TransferError::UnorderedReceives);
+    }
+    // check for serial number validity, if not valid, state stays the same
+    for (j, bill) in receives.iter().enumerate() {
+        let expected = state.next_serial + j as u64;
+        if bill.serial != expected {
+            return Err(TransferError::NonContiguousSerial {
+                expected,
+                got: bill.serial,
+            });
+        }
+    }
+    // check for duplicates in spends
+    check_no_duplicate_spends(spends)?;
+    // check for serial number already seen
+    for spent in spends.iter() {
+        count_comparisons(receives.len());
+        for received in receives.iter() {
+            if spent.serial == received.serial {
+                return Err(TransferError::SerialAlreadySpent {
+                    serial: spent.serial,
+                });
+            }
+        }
+    }
+    // if total amount received overflows or spends and receives have the same bill, state stays the same
+    let mut total_amount_received: u64 = 0;
+    for bill in receives.iter() {
+        if bill.amount == 0 {
+            return Err(TransferError::ZeroAmountOutput);
+        }
+        if bill.amount > MAX_BILL_AMOUNT {
+            return Err(TransferError::AmountTooLarge);
+        }
+        count_comparisons(spends.len());
+        if spends.contains(bill) {
+            return Err(TransferError::SpendAlsoReceived);
+        }
+        total_amount_received = mode
+            .add(total_amount_received, bill.amount)
+            .ok_or(TransferError::OutputOverflow)?;
+    }
+    // if spending the bill that doesn't exist, state stays the same
+    let total_amount_spent = total_of_existing(state, spends, mode)?;
+    // if total amount received is bigger than total amount spent, state stays the same
+    if total_amount_received > total_amount_spent {
+        return Err(TransferError::OutputsExceedInputs {
+            spent: total_amount_spent,
+            received: total_amount_received,
+        });
+    }
+    // with the tax on, the surplus must cover the tax, which is burned out of it
+    let mut surplus = total_amount_spent - total_amount_received;
+    if state.transfer_tax {
+        let spent = total_amount_spent as u128;
+        let after_tax = spent * (BASIS_POINTS - TAX_BPS) as u128 / BASIS_POINTS as u128;
+        let tax = (spent - after_tax) as u64;
+        if surplus < tax {
+            return Err(TransferError::InsufficientTax {
+                required: tax,
+                paid: surplus,
+            });
+        }
+        state.total_burned = state.total_burned.saturating_add(tax);
+        surplus -= tax;
+    }
+    // with recombination forbidden, no received bill may outgrow the largest spent bill
+    if state.forbid_recombination {
+        let largest_spent = spends.iter().map(|bill| bill.amount).max().unwrap_or(0);
+        if let Some(bill) = receives.iter().find(|bill| bill.amount > largest_spent) {
+            return Err(TransferError::OutputExceedsInput {
+                largest_spent,
+                received: bill.amount,
+            });
+        }
+    }
+    // with the window limit on, no user may send away more than the spend window allows
+    if state.limit_window_spend {
+        record_window_spends(state, spends, receives)?;
+    }
+    // all the conditions are satisifed, so we can insert received bills into hashset
+    // and remove spent bills from hashset
+    let parents: Vec<u64> = spends.iter().map(|bill| bill.serial).collect();
+    for bill in receives {
+        state.add_bill(Bill {
+            parents: parents.clone(),
+            ..bill.clone()
+        })?;
+    }
+    spends.iter().for_each(|bill| {
+        state.bills.remove(bill);
+    });
+    Ok(surplus)
+}
+
+/// Check that no spender of the transfer goes over [`MAX_WINDOW_SPEND`], and record what each
+/// of them sends away. Nothing is recorded if the check fails.
+fn record_window_spends(
+    state: &mut State,
+    spends: &[Bill],
+    receives: &[Bill],
+) -> Result<(), TransferError> {
+    let mut outflows: HashMap<User, u64> = HashMap::new();
+    for bill in spends.iter() {
+        let outflow = outflows.entry(bill.owner).or_default();
+        *outflow = outflow.saturating_add(bill.amount);
+    }
+    for bill in receives.iter() {
+        if let Some(outflow) = outflows.get_mut(&bill.owner) {
+            *outflow = outflow.saturating_sub(bill.amount);
+        }
+    }
+    for (user, outflow) in outflows.iter() {
+        let available = MAX_WINDOW_SPEND.saturating_sub(state.window_spend(user));
+        if *outflow > available {
+            return Err(TransferError::WindowSpendExceeded {
+                available,
+                requested: *outflow,
+            });
+        }
+    }
+    for (user, outflow) in outflows {
+        if outflow > 0 {
+            let epoch = state.epoch;
+            state
+                .recent_spends
+                .entry(user)
+                .or_default()
+                .push_back((epoch, outflow));
+        }
+    }
+    Ok(())
+}
+
+/// Reject spending any bill whose spend condition the given signers do not satisfy. Bills
+/// that are not in circulation are left for the existence check to report.
+fn check_spend_conditions(
+    state: &State,
+    spends: &[Bill],
+    signers: &[User],
+) -> Result<(), TransferError> {
+    for bill in spends.iter() {
+        let Some(circulating) = state.bills.get(bill) else {
+            continue;
+        };
+        if let Some(condition) = &circulating.spend_condition {
+            if !condition.permits(&circulating.owner, signers, state.epoch) {
+                return Err(TransferError::Unauthorized);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Like [`check_spend_conditions`], except that a signer may also spend a bill in place of its
+/// owner when the owner has approved them for enough value. Returns how much of each
+/// (owner, spender) allowance the spends use up.
+fn check_delegated_spends(
+    state: &State,
+    spends: &[Bill],
+    signers: &[User],
+) -> Result<HashMap<(User, User), u64>, TransferError> {
+    let mut used: HashMap<(User, User), u64> = HashMap::new();
+    for bill in spends.iter() {
+        let Some(circulating) = state.bills.get(bill) else {
+            continue;
+        };
+        let Some(condition) = &circulating.spend_condition else {
+            continue;
+        };
+        if condition.permits(&circulating.owner, signers, state.epoch) {
+            continue;
+        }
+        // an allowance only ever stands in for the owner's own signature
+        let owner = circulating.owner;
+        let spender = signers
+            .iter()
+            .find(|signer| state.allowance(&owner, signer) > 0);
+        match spender {
+            Some(spender) if condition.permits(&owner, &[owner], state.epoch) => {
+                let total = used.entry((owner, *spender)).or_default();
+                *total = total.saturating_add(circulating.amount);
+            }
+            _ => return Err(TransferError::Unauthorized),
+        }
+    }
+    for ((owner, spender), requested) in used.iter() {
+        let available = state.allowance(owner, spender);
+        if *requested > available {
+            return Err(TransferError::AllowanceExceeded {
+                available,
+                requested: *requested,
+            });
+        }
+    }
+    Ok(used)
+}
+
+/// Reject a list of spends that contains the same bill more than once
+fn check_no_duplicate_spends(spends: &[Bill]) -> Result<(), TransferError> {
+    let mut seen = HashSet::with_capacity(spends.len());
+    for bill in spends.iter() {
+        count_comparisons(1);
+        if !seen.insert(bill) {
+            return Err(TransferError::DuplicateSpend {
+                serial: bill.serial,
+            });
+        }
+    }
+    Ok(())
+}
+
+thread_local! {
+    /// How many bill lookups and comparisons validation has made on this thread, as reported
+    /// by [`validate_instrumented`]
+    static COMPARISONS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Record that validation made the given number of bill lookups or comparisons
+fn count_comparisons(n: usize) {
+    COMPARISONS.with(|comparisons| comparisons.set(comparisons.get() + n));
+}
+
+/// Validate a transition, also reporting how many bill lookups and comparisons the checks on
+/// its spends and receives made along the way. Useful for seeing how validation cost grows
+/// with the size of a transfer.
+pub fn validate_instrumented(
+    state: &State,
+    t: &CashTransaction,
+) -> (Result<(), TransferError>, usize) {
+    COMPARISONS.with(|comparisons| comparisons.set(0));
+    let result = DigitalCashSystem::try_next_state(state, t).map(|_| ());
+    (result, COMPARISONS.with(Cell::get))
+}
+
+/// The total value of the given bills, provided every one of them is in circulation and has
+/// not expired
+fn total_of_existing(
+    state: &State,
+    spends: &[Bill],
+    mode: OverflowMode,
+) -> Result<u64, TransferError> {
+    let mut total = 0;
+    for bill in spends.iter() {
+        count_comparisons(1);
+        let Some(circulating) = state.bills.get(bill) else {
+            return Err(TransferError::NonexistentBill {
+                serial: bill.serial,
+            });
+        };
+        if circulating.expires_at <= state.epoch {
+            return Err(TransferError::ExpiredBill {
+                serial: bill.serial,
+            });
+        }
+        total = mode
+            .add(total, bill.amount)
+            .ok_or(TransferError::InputOverflow)?;
+    }
+    Ok(total)
+}
+
+/// We model this system as a state machine with several possible transitions. Invalid
+/// transitions leave the state unchanged.
+impl StateMachine for DigitalCashSystem {
+    type State = State;
+    type Transition = CashTransaction;
+
+    fn next_state(starting_state: &Self::State, t: &Self::Transition) -> Self::State {
+        DigitalCashSystem::try_next_state(starting_state, t)
+            .unwrap_or_else(|_| starting_state.clone())
+    }
+}
+
+/// The digital cash system with saturating amount arithmetic. Where [`DigitalCashSystem`]
+/// rejects a transfer whose amounts add up to more than a `u64` can hold, this one clamps the
+/// totals to `u64::MAX` and judges the transfer on the clamped values.
+pub struct SaturatingDigitalCashSystem;
+
+impl StateMachine for SaturatingDigitalCashSystem {
+    type State = State;
+    type Transition = CashTransaction;
+
+    fn next_state(starting_state: &State, t: &CashTransaction) -> State {
+        DigitalCashSystem::apply_with_mode(starting_state, t, OverflowMode::Saturating)
+            .map(|(state, _)| state)
+            .unwrap_or_else(|_| starting_state.clone())
+    }
+}
+
+/// The digital cash system operating on an [`OrderedState`]. Transitions are validated and
+/// applied by exactly the same logic as [`DigitalCashSystem`].
+pub struct OrderedDigitalCashSystem;
+
+impl StateMachine for OrderedDigitalCashSystem {
+    type State = OrderedState;
+    type Transition = CashTransaction;
+
+    fn next_state(starting_state: &OrderedState, t: &CashTransaction) -> OrderedState {
+        let state = State::from(starting_state.clone());
+        DigitalCashSystem::next_state(&state, t).into()
+    }
+}
+
+/// A digital cash state that also remembers the identifiers of every transaction applied to
+/// it, so that replaying one of them has no effect
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ReplayProtectedState {
+    state: State,
+    /// The identifiers, as computed by [`tx_id`], of the transactions applied so far
+    seen_tx_ids: HashSet<[u8; 32]>,
+}
+
+impl ReplayProtectedState {
+    /// The underlying ledger
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// Whether the transaction with the given identifier has already been applied
+    pub fn has_seen(&self, id: &[u8; 32]) -> bool {
+        self.seen_tx_ids.contains(id)
+    }
+}
+
+impl From<State> for ReplayProtectedState {
+    fn from(state: State) -> Self {
+        ReplayProtectedState {
+            state,
+            seen_tx_ids: HashSet::new(),
+        }
+    }
+}
+
+/// The digital cash system with replay protection. A transaction that has already been
+/// applied once is ignored, so minting the same amount to the same user twice only creates
+/// one bill. Rejected transactions are not remembered and may still apply later.
+///
+/// Ticks, nops and demurrage carry nothing to tell one occurrence from the next, and their
+/// effect depends on the state rather than on the transaction, so they are never treated as
+/// replays. Neither are the administrative transitions that set something to a value, such as
+/// pausing, approving and capping, since repeating them can only set the same value again.
+pub struct ReplayProtectedDigitalCashSystem;
+
+impl StateMachine for ReplayProtectedDigitalCashSystem {
+    type State = ReplayProtectedState;
+    type Transition = CashTransaction;
+
+    fn next_state(
+        starting_state: &ReplayProtectedState,
+        t: &CashTransaction,
+    ) -> ReplayProtectedState {
+        let repeatable = matches!(
+            t,
+            CashTransaction::Tick
+                | CashTransaction::Nop
+                | CashTransaction::ApplyDemurrage { .. }
+                | CashTransaction::SetPaused { .. }
+                | CashTransaction::Approve { .. }
+                | CashTransaction::CapBalance { .. }
+        );
+        let id = tx_id(t);
+        if !repeatable && starting_state.has_seen(&id) {
+            return starting_state.clone();
+        }
+        match DigitalCashSystem::try_next_state(&starting_state.state, t) {
+            Ok(state) => {
+                let mut seen_tx_ids = starting_state.seen_tx_ids.clone();
+                if !repeatable {
+                    seen_tx_ids.insert(id);
+                }
+                ReplayProtectedState { state, seen_tx_ids }
+            }
+            Err(_) => starting_state.clone(),
+        }
+    }
+}
+
+/// A transaction of any kind bound to the chain it was made for, so that it cannot be
+/// replayed on a fork that shares its history
+#[derive(Hash)]
+pub struct ChainBoundTransaction {
+    /// The chain the transaction was made for
+    pub chain_id: u32,
+    /// The transaction itself
+    pub transaction: CashTransaction,
+}
+
+/// The digital cash system with every transaction bound to a chain. A transaction only
+/// applies to a state with the same chain id, so forks that share a history cannot replay
+/// each other's transactions.
+pub struct ChainBoundDigitalCashSystem;
+
+impl ChainBoundDigitalCashSystem {
+    /// Calculate the resulting state when this state undergoes the given transition, or the
+    /// reason that the transition is not valid from this state.
+    pub fn try_next_state(
+        starting_state: &State,
+        t: &ChainBoundTransaction,
+    ) -> Result<State, TransferError> {
+        if t.chain_id != starting_state.chain_id {
+            return Err(TransferError::WrongChain);
+        }
+        DigitalCashSystem::try_next_state(starting_state, &t.transaction)
+    }
+}
+
+impl StateMachine for ChainBoundDigitalCashSystem {
+    type State = State;
+    type Transition = ChainBoundTransaction;
+
+    fn next_state(starting_state: &State, t: &ChainBoundTransaction) -> State {
+        ChainBoundDigitalCashSystem::try_next_state(starting_state, t)
+            .unwrap_or_else(|_| starting_state.clone())
+    }
+}
+
+/// Memoizes the outcome of validating a transition against a particular state. Entries are
+/// keyed by the state root as well as the transaction hash, so once the state changes any
+/// previously cached outcomes are simply never looked up again.
+pub struct ValidationCache {
+    results: HashMap<(u64, u64), Result<(), TransferError>>,
+    /// How many times the transition logic actually had to run
+    validations: usize,
+}
+
+impl ValidationCache {
+    pub fn new() -> Self {
+        ValidationCache {
+            results: HashMap::new(),
+            validations: 0,
+        }
+    }
+
+    /// Check whether the given transition is valid from the given state, reusing the
+    /// previous outcome if this exact pair has been validated before.
+    pub fn validate(&mut self, state: &State, t: &CashTransaction) -> Result<(), TransferError> {
+        let key = (state.state_root(), hash(t));
+        if let Some(result) = self.results.get(&key) {
+            return result.clone();
+        }
+
+        self.validations += 1;
+        let result = DigitalCashSystem::try_next_state(state, t).map(|_| ());
+        self.results.insert(key, result.clone());
+        result
+    }
+
+    /// The number of validations that were not served from the cache
+    pub fn validations(&self) -> usize {
+        self.validations
+    }
+}
+
+/// The serials of the existing bills that a transaction reads or modifies. Mints only create
+/// new bills, so they touch nothing.
+pub fn touched_serials(t: &CashTransaction) -> Vec<u64> {
+    match t {
+        CashTransaction::Mint { .. }
+        | CashTransaction::MintAndSend { .. }
+        | CashTransaction::Airdrop { .. }
+        | CashTransaction::Refund { .. }
+        | CashTransaction::Tick
+        | CashTransaction::Nop
+        | CashTransaction::Approve { .. }
+        | CashTransaction::SetPaused { .. }
+        | CashTransaction::CapBalance { .. }
+        | CashTransaction::ApplyDemurrage { .. }
+        | CashTransaction::RotateKey { .. } => Vec::new(),
+        CashTransaction::Transfer { spends, .. }
+        | CashTransaction::SignedTransfer { spends, .. }
+        | CashTransaction::PooledTransfer { spends, .. }
+        | CashTransaction::Burn { spends, .. }
+        | CashTransaction::PartialBurn { spends, .. } => {
+            spends.iter().map(|bill| bill.serial).collect()
+        }
+        CashTransaction::ShareBills { spends, .. } => {
+            spends.iter().map(|bill| bill.serial).collect()
+        }
+        CashTransaction::ExitShare { serial, .. } => vec![*serial],
+        CashTransaction::Escrow { bill, .. } => vec![bill.serial],
+        CashTransaction::Claim { serial, .. } | CashTransaction::ReclaimEscrow { serial } => {
+            vec![*serial]
+        }
+        CashTransaction::ConditionalTransfer { condition, inner } => {
+            let mut serials = touched_serials(inner);
+            if let Condition::BillExists(serial) = condition {
+                if !serials.contains(serial) {
+                    serials.push(*serial);
+                }
+            }
+            serials
+        }
+    }
+}
+
+/// Whether the transaction is valid but leaves every user's balance exactly as it was, such
+/// as a transfer that spends a bill only to hand the same value straight back to its owner.
+/// Such transactions only churn serials, so a mempool may want to deprioritize them. Invalid
+/// transactions are not no-ops; they are simply rejected.
+pub fn is_economic_noop(state: &State, t: &CashTransaction) -> bool {
+    // every user's balance, leaving out users who hold nothing
+    fn balances(state: &State) -> HashMap<User, u64> {
+        let mut balances = HashMap::new();
+        for bill in state.bills.iter() {
+            let balance: &mut u64 = balances.entry(bill.owner).or_default();
+            *balance = balance.saturating_add(bill.amount);
+        }
+        for shared in state.shared_bills.values() {
+            for (owner, shares) in shared.shares.iter() {
+                let balance: &mut u64 = balances.entry(*owner).or_default();
+                *balance = balance.saturating_add(*shares);
+            }
+        }
+        balances
+    }
+
+    match DigitalCashSystem::try_next_state(state, t) {
+        Ok(next_state) => balances(state) == balances(&next_state),
+        Err(_) => false,
+    }
+}
+
+/// Whether every output of a transfer goes to a user who owns one of the bills it spends, so
+/// that the money only moves around within the same set of users rather than paying anyone
+/// new. Input owners are looked up in the state, so spends of bills that are not in
+/// circulation contribute no owners. Transactions that do not list their outputs are never
+/// reshuffles.
+pub fn is_internal_reshuffle(state: &State, t: &CashTransaction) -> bool {
+    match t {
+        CashTransaction::Transfer {
+            spends, receives, ..
+        }
+        | CashTransaction::SignedTransfer {
+            spends, receives, ..
+        }
+        | CashTransaction::PooledTransfer {
+            spends, receives, ..
+        } => {
+            let owners: HashSet<User> = spends
+                .iter()
+                .filter_map(|spend| state.bills.iter().find(|bill| bill.serial == spend.serial))
+                .map(|bill| bill.owner)
+                .collect();
+            !owners.is_empty() && receives.iter().all(|bill| owners.contains(&bill.owner))
+        }
+        CashTransaction::ConditionalTransfer { inner, .. } => is_internal_reshuffle(state, inner),
+        _ => false,
+    }
+}
+
+/// The state cut down to only the bills the transaction touches, as long as that still gives
+/// the transaction the same outcome, so that a failure can be studied without the rest of
+/// the ledger. Everything other than the bills is kept. When the cut-down state would
+/// behave differently, the full state is returned instead.
+pub fn minimal_repro(state: &State, t: &CashTransaction) -> State {
+    let touched = touched_serials(t);
+    let mut repro = state.clone();
+    repro.bills.retain(|bill| touched.contains(&bill.serial));
+    repro
+        .shared_bills
+        .retain(|serial, _| touched.contains(serial));
+    repro.escrows.retain(|serial, _| touched.contains(serial));
+
+    let outcome = |state: &State| DigitalCashSystem::try_next_state(state, t).map(|_| ());
+    if outcome(&repro) == outcome(state) {
+        repro
+    } else {
+        state.clone()
+    }
+}
+
+/// Bills worth less than this are dust, too small to be worth the cost of spending
+pub const DUST_THRESHOLD: u64 = 10;
+
+/// Something about a valid transaction that its author may not have intended
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Warning {
+    /// The given amount will leave circulation
+    ValueWillBurn(u64),
+    /// A bill worth only the given amount, below [`DUST_THRESHOLD`], will be created
+    CreatesDust(u64),
+    /// The transfer pays nobody new, as judged by [`is_internal_reshuffle`]
+    InternalReshuffle,
+}
+
+/// Validate a transaction, and for a valid one, also point out anything about it that is
+/// allowed but likely a mistake. Rejected transactions carry no warnings.
+pub fn validate_with_warnings(
+    state: &State,
+    t: &CashTransaction,
+) -> (Result<(), TransferError>, Vec<Warning>) {
+    let next_state = match DigitalCashSystem::try_next_state(state, t) {
+        Ok(next_state) => next_state,
+        Err(e) => return (Err(e), Vec::new()),
+    };
+
+    let mut warnings = Vec::new();
+    // value moved into escrow or a co-owned bill leaves the supply without being destroyed,
+    // so only count what was burned and the fee a transfer leaves unreceived. Any transfer
+    // tax is burned out of that fee, so it must not be counted twice.
+    let burned = next_state
+        .total_burned
+        .saturating_sub(state.total_burned)
+        .max(fee_for(t));
+    if burned > 0 {
+        warnings.push(Warning::ValueWillBurn(burned));
+    }
+    let (_, created) = state.serial_diff(&next_state);
+    for bill in next_state.bills_sorted() {
+        if created.contains(&bill.serial) && bill.amount < DUST_THRESHOLD {
+            warnings.push(Warning::CreatesDust(bill.amount));
+        }
+    }
+    if is_internal_reshuffle(state, t) {
+        warnings.push(Warning::InternalReshuffle);
+    }
+    (Ok(()), warnings)
+}
+
+/// Whether the two transactions leave every user with the same balance when applied to the
+/// given state, even if they split it into different bills or number them differently. A
+/// rejected transaction leaves the state unchanged, so two rejected transactions always
+/// have the same effect.
+pub fn same_effect(state: &State, a: &CashTransaction, b: &CashTransaction) -> bool {
+    DigitalCashSystem::next_state(state, a).to_balance_map()
+        == DigitalCashSystem::next_state(state, b).to_balance_map()
+}
+
+/// The bills a transfer will give the given user, including any change returned to them, in
+/// the order the transfer lists them. Transactions that do not list their outputs give
+/// nothing.
+pub fn outputs_to<'a>(t: &'a CashTransaction, user: &User) -> Vec<&'a Bill> {
+    match t {
+        CashTransaction::Transfer { receives, .. }
+        | CashTransaction::SignedTransfer { receives, .. }
+        | CashTransaction::PooledTransfer { receives, .. } => {
+            receives.iter().filter(|bill| bill.owner == *user).collect()
+        }
+        CashTransaction::PartialBurn { change, .. } if change.owner == *user => vec![change],
+        CashTransaction::ConditionalTransfer { inner, .. } => outputs_to(inner, user),
+        _ => Vec::new(),
+    }
+}
+
+/// What a transaction consumes, either by spending a bill in circulation, by releasing a bill
+/// from escrow, or by exiting a co-owner's shares. Each is a serial along with the co-owner
+/// whose shares are exited, if that is all that is consumed, since different co-owners can
+/// each exit the same co-owned bill. Unlike [`touched_serials`], bills that are only read by
+/// a condition are not included.
+fn spent_claims(t: &CashTransaction) -> Vec<(u64, Option<User>)> {
+    match t {
+        CashTransaction::ConditionalTransfer { inner, .. } => spent_claims(inner),
+        CashTransaction::ExitShare {
+            serial,
+            shareholder,
+        } => vec![(*serial, Some(*shareholder))],
+        _ => touched_serials(t)
+            .into_iter()
+            .map(|serial| (serial, None))
+            .collect(),
+    }
+}
+
+/// Every pair of transactions in the batch that spend a common serial, as the indices of the
+/// two transactions, earlier first, along with that serial. A block containing any such pair
+/// is invalid.
+pub fn find_double_spends(txs: &[CashTransaction]) -> Vec<(usize, usize, u64)> {
+    let spent: Vec<Vec<(u64, Option<User>)>> = txs.iter().map(spent_claims).collect();
+    let mut double_spends = Vec::new();
+    for (i, first) in spent.iter().enumerate() {
+        for (j, second) in spent.iter().enumerate().skip(i + 1) {
+            for claim in first {
+                if second.contains(claim) {
+                    double_spends.push((i, j, claim.0));
+                }
+            }
+        }
+    }
+    double_spends
+}
+
+/// Every serial that more than one of the pending transactions spends, mapped to the indices
+/// of those transactions in ascending order. Of the transactions spending a bill, or exiting
+/// the same co-owner's shares, at most one can ever be applied. Different co-owners exiting
+/// the same co-owned bill do not compete.
+pub fn contended_serials(pending: &[CashTransaction]) -> HashMap<u64, Vec<usize>> {
+    let mut spenders: HashMap<(u64, Option<User>), Vec<usize>> = HashMap::new();
+    for (i, t) in pending.iter().enumerate() {
+        for claim in spent_claims(t) {
+            let indices = spenders.entry(claim).or_default();
+            if indices.last() != Some(&i) {
+                indices.push(i);
+            }
+        }
+    }
+    let mut contended: HashMap<u64, Vec<usize>> = HashMap::new();
+    for ((serial, _), indices) in spenders {
+        if indices.len() > 1 {
+            let contenders = contended.entry(serial).or_default();
+            contenders.extend(indices);
+            contenders.sort();
+            contenders.dedup();
+        }
+    }
+    contended
+}
+
+/// The ways a set of genesis bills can be malformed
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum GenesisError {
+    /// Two bills share a serial
+    DuplicateSerial(u64),
+    /// The serials are not dense from zero; the given serial was expected but not found
+    SerialGap { expected: u64, got: u64 },
+    /// A bill is worth nothing
+    ZeroAmount { serial: u64 },
+    /// The total value of the bills overflows
+    SupplyOverflow,
+}
+
+/// Check that the given bills form a well formed genesis state: serials are unique and dense
+/// from zero, every bill is worth something, and the total supply does not overflow.
+pub fn validate_genesis(bills: &[Bill]) -> Result<(), GenesisError> {
+    let mut serials: Vec<u64> = bills.iter().map(|bill| bill.serial).collect();
+    serials.sort();
+    for pair in serials.windows(2) {
+        if pair[0] == pair[1] {
+            return Err(GenesisError::DuplicateSerial(pair[0]));
+        }
+    }
+    for (expected, serial) in serials.iter().enumerate() {
+        if *serial != expected as u64 {
+            return Err(GenesisError::SerialGap {
+                expected: expected as u64,
+                got: *serial,
+            });
+        }
+    }
+
+    let mut total: u64 = 0;
+    for bill in bills.iter() {
+        if bill.amount == 0 {
+            return Err(GenesisError::ZeroAmount {
+                serial: bill.serial,
+            });
+        }
+        total = total
+            .checked_add(bill.amount)
+            .ok_or(GenesisError::SupplyOverflow)?;
+    }
+    Ok(())
+}
+
+/// The weight of minting a single bill
+pub const MINT_WEIGHT: u64 = 10;
+/// The weight of every bill a transaction spends
+pub const INPUT_WEIGHT: u64 = 5;
+/// The weight of every bill a transaction creates
+pub const OUTPUT_WEIGHT: u64 = 3;
+/// The weight of evaluating a condition
+pub const CONDITION_WEIGHT: u64 = 2;
+/// The weight of advancing the epoch
+pub const TICK_WEIGHT: u64 = 1;
+/// The weight of an administrative transition such as pausing the system
+pub const ADMIN_WEIGHT: u64 = 1;
+
+/// The weight of a transaction, a rough measure of how much work it takes to validate and
+/// apply. Block builders use it to decide how many transactions fit in a block.
+///
+/// The weight can be zero, for example for a transfer with no spends and no receives, so
+/// anything dividing by it or comparing fee rates must handle that case.
+pub fn weight(t: &CashTransaction) -> u64 {
+    match t {
+        CashTransaction::Mint { .. }
+        | CashTransaction::MintAndSend { .. }
+        | CashTransaction::Refund { .. } => MINT_WEIGHT,
+        CashTransaction::Airdrop { recipients, .. } => MINT_WEIGHT * recipients.len() as u64,
+        CashTransaction::Transfer {
+            spends, receives, ..
+        }
+        | CashTransaction::SignedTransfer {
+            spends, receives, ..
+        }
+        | CashTransaction::PooledTransfer { spends, receives } => {
+            INPUT_WEIGHT * spends.len() as u64 + OUTPUT_WEIGHT * receives.len() as u64
+        }
+        CashTransaction::Burn { spends, .. } => INPUT_WEIGHT * spends.len() as u64,
+        CashTransaction::PartialBurn { spends, .. } => {
+            INPUT_WEIGHT * spends.len() as u64 + OUTPUT_WEIGHT
+        }
+        CashTransaction::ShareBills { spends, .. } => {
+            INPUT_WEIGHT * spends.len() as u64 + OUTPUT_WEIGHT
+        }
+        CashTransaction::ExitShare { .. } => INPUT_WEIGHT + OUTPUT_WEIGHT,
+        CashTransaction::Escrow { .. } => INPUT_WEIGHT,
+        CashTransaction::Claim { .. } | CashTransaction::ReclaimEscrow { .. } => {
+            CONDITION_WEIGHT + OUTPUT_WEIGHT
+        }
+        CashTransaction::Tick => TICK_WEIGHT,
+        CashTransaction::Nop => 0,
+        CashTransaction::Approve { .. }
+        | CashTransaction::SetPaused { .. }
+        | CashTransaction::CapBalance { .. }
+        | CashTransaction::ApplyDemurrage { .. }
+        | CashTransaction::RotateKey { .. } => ADMIN_WEIGHT,
+        CashTransaction::ConditionalTransfer { inner, .. } => CONDITION_WEIGHT + weight(inner),
+    }
+}
+
+/// The fee a transaction pays, which is the value it spends but does not pass on to any
+/// recipient. Only plain transfers pay fees; the surplus of a pooled transfer goes to the
+/// burn pool instead. The fee is computed from the amounts the transaction
+/// claims, without checking them against any state.
+pub fn fee_for(t: &CashTransaction) -> u64 {
+    match t {
+        CashTransaction::Transfer {
+            spends, receives, ..
+        }
+        | CashTransaction::SignedTransfer {
+            spends, receives, ..
+        } => {
+            let spent = spends
+                .iter()
+                .fold(0u64, |total, bill| total.saturating_add(bill.amount));
+            let received = receives
+                .iter()
+                .fold(0u64, |total, bill| total.saturating_add(bill.amount));
+            spent.saturating_sub(received)
+        }
+        CashTransaction::ConditionalTransfer { inner, .. } => fee_for(inner),
+        _ => 0,
+    }
+}
+
+/// The total fee a batch of transactions pays, as computed by [`fee_for`], which a block
+/// producer collects as its reward. The total saturates at `u64::MAX`.
+pub fn total_fees(txs: &[CashTransaction]) -> u64 {
+    txs.iter()
+        .fold(0, |total: u64, t| total.saturating_add(fee_for(t)))
+}
+
+/// Where the value spent by a transfer goes. A valid transfer always balances:
+/// `spent == received + fee + burned`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AccountingBreakdown {
+    /// The total value of the spent bills
+    pub spent: u64,
+    /// The total value of the received bills
+    pub received: u64,
+    /// The fee paid, as computed by [`fee_for`], less any transfer tax
+    pub fee: u64,
+    /// The value destroyed or captured into the burn pool
+    pub burned: u64,
+}
+
+/// Account for where the value spent by a transfer goes, or `None` if the transaction is not
+/// a valid transfer from this state. Conditional transfers are accounted by their inner
+/// transfer.
+pub fn accounting_breakdown(state: &State, t: &CashTransaction) -> Option<AccountingBreakdown> {
+    let mut inner = t;
+    while let CashTransaction::ConditionalTransfer { inner: nested, .. } = inner {
+        inner = nested;
+    }
+    let (CashTransaction::Transfer {
+        spends, receives, ..
+    }
+    | CashTransaction::SignedTransfer {
+        spends, receives, ..
+    }
+    | CashTransaction::PooledTransfer { spends, receives }) = inner
+    else {
+        return None;
+    };
+    let next_state = DigitalCashSystem::try_next_state(state, t).ok()?;
+
+    // a transfer tax is burned out of the surplus, so it is not part of the fee
+    let burned = (next_state.total_burned - state.total_burned)
+        + next_state.burn_pool.saturating_sub(state.burn_pool);
+    let breakdown = AccountingBreakdown {
+        spent: spends.iter().map(|bill| bill.amount).sum(),
+        received: receives.iter().map(|bill| bill.amount).sum(),
+        fee: fee_for(t).saturating_sub(burned),
+        burned,
+    };
+    let accounted = breakdown
+        .received
+        .checked_add(breakdown.fee)?
+        .checked_add(breakdown.burned)?;
+    (accounted == breakdown.spent).then_some(breakdown)
+}
+
+/// Choose which transactions from the pool go into a block built on top of the given state.
+/// Like a fee market, transactions paying the most fee per unit of weight are considered
+/// first, with ties going to the earlier transaction in the pool. Each is included whenever
+/// it fits in the remaining weight and is valid on top of the transactions already included,
+/// so the block never contains two transactions spending the same bill. Returns the pool
+/// indices of the chosen transactions in the order they are applied.
+pub fn pack_block(state: &State, pool: &[CashTransaction], max_weight: u64) -> Vec<usize> {
+    let mut block = Vec::new();
+    let mut state = state.clone();
+    let mut block_weight = 0;
+
+    // compare fee rates by cross multiplying rather than dividing. That is only a total order
+    // between positive weights, so weightless transactions are considered last, and ties go
+    // to the earlier transaction.
+    let mut candidates: Vec<(usize, u128, u128)> = pool
+        .iter()
+        .enumerate()
+        .map(|(i, t)| (i, fee_for(t) as u128, weight(t) as u128))
+        .collect();
+    candidates.sort_by(|(i, fee_a, weight_a), (j, fee_b, weight_b)| {
+        (*weight_a == 0)
+            .cmp(&(*weight_b == 0))
+            .then((fee_b * weight_a).cmp(&(fee_a * weight_b)))
+            .then(i.cmp(j))
+    });
+
+    for (i, _, _) in candidates {
+        let t = &pool[i];
+        let tx_weight = weight(t);
+        if block_weight + tx_weight > max_weight {
+            continue;
+        }
+        if let Ok(next_state) = DigitalCashSystem::try_next_state(&state, t) {
+            state = next_state;
+            block_weight += tx_weight;
+            block.push(i);
+        }
+    }
+    block
+}
+
+/// The net value that moved from one user to another over a log of transactions applied
+/// on top of the genesis state. Positive values mean more moved from `from` to `to` than the
+/// other way around. Rejected transactions move nothing.
+///
+/// A transfer can have many spenders and many recipients, so the value each recipient gets
+/// is attributed to the spenders in proportion to how much each of them put in (rounding
+/// down).
+pub fn net_flow(genesis: &State, txs: &[CashTransaction], from: &User, to: &User) -> i128 {
+    // the value that moved from `a` to `b` in a single transfer
+    fn flow(spends: &[Bill], receives: &[Bill], a: &User, b: &User) -> i128 {
+        let total_spent: i128 = spends.iter().map(|bill| bill.amount as i128).sum();
+        if a == b || total_spent == 0 {
+            return 0;
+        }
+        let spent_by_a: i128 = spends
+            .iter()
+            .filter(|bill| bill.owner == *a)
+            .map(|bill| bill.amount as i128)
+            .sum();
+        let received_by_b: i128 = receives
+            .iter()
+            .filter(|bill| bill.owner == *b)
+            .map(|bill| bill.amount as i128)
+            .sum();
+        received_by_b * spent_by_a / total_spent
+    }
+
+    let mut state = genesis.clone();
+    let mut net = 0;
+    for t in txs.iter() {
+        let Ok(next_state) = DigitalCashSystem::try_next_state(&state, t) else {
+            continue;
+        };
+        // a conditional transfer that applied moves value exactly like its inner transfer
+        let mut applied = t;
+        while let CashTransaction::ConditionalTransfer { inner, .. } = applied {
+            applied = inner;
+        }
+        if let CashTransaction::Transfer {
+            spends, receives, ..
+        }
+        | CashTransaction::SignedTransfer {
+            spends, receives, ..
+        }
+        | CashTransaction::PooledTransfer { spends, receives } = applied
+        {
+            net += flow(spends, receives, from, to) - flow(spends, receives, to, from);
+        }
+        state = next_state;
+    }
+    net
+}
+
+/// A finite selection of the transitions available from the given state: minting each of
+/// the given amounts to each user, handing each circulating bill whole to each other user,
+/// burning each circulating bill, and ticking.
+fn candidate_transitions(state: &State, amounts: &[u64]) -> Vec<CashTransaction> {
+    let mut transitions = Vec::new();
+    for minter in User::ALL {
+        for amount in amounts.iter() {
+            transitions.push(CashTransaction::Mint {
+                minter,
+                amount: *amount,
+            });
+        }
+    }
+    for bill in state.bills_sorted() {
+        for recipient in User::ALL.into_iter().filter(|user| *user != bill.owner) {
+            transitions.push(CashTransaction::Transfer {
+                spends: vec![bill.clone()],
+                receives: vec![Bill::new(recipient, bill.amount, state.next_serial)],
+                memo: Vec::new(),
+            });
+        }
+        transitions.push(CashTransaction::Burn {
+            signer: bill.owner,
+            spends: vec![bill.clone()],
+        });
+    }
+    transitions.push(CashTransaction::Tick);
+    transitions
+}
+
+/// Whether the `to` state can be reached from the `from` state in at most `max_depth`
+/// transitions. States are compared by their circulating bills and next serial number, and
+/// the search explores mints of the amounts found in the target, whole-bill payments, burns
+/// and ticks breadth first, so it may miss targets that need other transitions.
+pub fn is_reachable(from: &State, to: &State, max_depth: usize) -> bool {
+    let circulation = |state: &State| hash(&(state.bills_sorted(), state.next_serial));
+    let target = circulation(to);
+    let amounts = to.denominations();
+    let mut seen = HashSet::from([from.state_root()]);
+    let mut frontier = vec![from.clone()];
+    for depth in 0..=max_depth {
+        if frontier.iter().any(|state| circulation(state) == target) {
+            return true;
+        }
+        if depth == max_depth {
+            break;
+        }
+        let mut next_frontier = Vec::new();
+        for state in frontier.iter() {
+            for t in candidate_transitions(state, &amounts) {
+                let Ok(next_state) = DigitalCashSystem::try_next_state(state, &t) else {
+                    continue;
+                };
+                if seen.insert(next_state.state_root()) {
+                    next_frontier.push(next_state);
+                }
+            }
+        }
+        frontier = next_frontier;
+    }
+    false
+}
+
+/// The index of the transaction in the log that created the bill with the given serial, or
+/// `None` if no transaction did. The log is replayed from an empty state, since a mint does
+/// not name the serial it creates. Rejected transactions create nothing.
+pub fn creator_of(log: &[CashTransaction], serial: u64) -> Option<usize> {
+    let exists = |state: &State| {
+        state.bills.iter().any(|bill| bill.serial == serial)
+            || state.shared_bills.contains_key(&serial)
+    };
+    let mut state = State::new();
+    for (i, t) in log.iter().enumerate() {
+        let Ok(next_state) = DigitalCashSystem::try_next_state(&state, t) else {
+            continue;
+        };
+        if !exists(&state) && exists(&next_state) {
+            return Some(i);
+        }
+        state = next_state;
+    }
+    None
+}
+
+/// Lazily apply each transaction in turn, yielding a copy of the state after every one, so
+/// that a viewer can animate the ledger as it evolves. Rejected transactions yield the state
+/// unchanged, just as [`StateMachine::next_state`] leaves it.
+pub fn apply_streaming<'a>(
+    start: State,
+    txs: &'a [CashTransaction],
+) -> impl Iterator<Item = State> + 'a {
+    txs.iter().scan(start, |state, t| {
+        *state = DigitalCashSystem::next_state(state, t);
+        Some(state.clone())
+    })
+}
+
+/// The order in which a [`bank_run`] serves redeemers
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RedeemOrder {
+    /// In the order they are listed
+    AsListed,
+    /// Those with the largest balance first
+    LargestFirst,
+    /// Those with the smallest balance first
+    SmallestFirst,
+}
+
+/// Simulate a run on the issuer, with the burn pool serving as its reserve. Each redeemer in
+/// turn burns all of their bills and is paid their value out of the reserve, until a
+/// redeemer's claim is more than the reserve has left. From then on the issuer is out of
+/// reserve, so that redeemer and everyone after them go unpaid and keep their bills. Returns
+/// the final state along with the unpaid redeemers, in the order they were served.
+/// Redeemers with nothing to redeem are skipped, and those whose bills cannot be burned go
+/// unpaid.
+pub fn bank_run(state: &State, redeemers: &[User], order: RedeemOrder) -> (State, Vec<User>) {
+    let mut queue: Vec<User> = redeemers.to_vec();
+    match order {
+        RedeemOrder::AsListed => {}
+        RedeemOrder::LargestFirst => {
+            queue.sort_by_key(|user| std::cmp::Reverse(state.balance_of(user)))
+        }
+        RedeemOrder::SmallestFirst => queue.sort_by_key(|user| state.balance_of(user)),
+    }
+
+    let mut state = state.clone();
+    let mut unpaid = Vec::new();
+    let mut exhausted = false;
+    for user in queue {
+        let spends: Vec<Bill> = state
+            .bills_sorted()
+            .into_iter()
+            .filter(|bill| bill.owner == user)
+            .cloned()
+            .collect();
+        if spends.is_empty() {
+            continue;
+        }
+        let claim = spends
+            .iter()
+            .fold(0, |total: u64, bill| total.saturating_add(bill.amount));
+        let burn = CashTransaction::Burn {
+            signer: user,
+            spends,
+        };
+        exhausted |= claim > state.burn_pool;
+        match DigitalCashSystem::try_next_state(&state, &burn) {
+            Ok(next_state) if !exhausted => {
+                state = next_state;
+                state.burn_pool -= claim;
+            }
+            _ => unpaid.push(user),
+        }
+    }
+    (state, unpaid)
+}
+
+/// Whether the money in circulation is enough to cover the given claims against the issuer
+pub fn is_solvent(state: &State, external_claims: u64) -> bool {
+    state.total_supply() >= external_claims
+}
+
+/// How many times over the money in circulation covers the given claims against the issuer.
+/// A ratio of at least one means the issuer is solvent. With no claims at all, the ratio is
+/// infinite.
+pub fn reserve_ratio(state: &State, claims: u64) -> f64 {
+    if claims == 0 {
+        return f64::INFINITY;
+    }
+    state.total_supply() as f64 / claims as f64
+}
+
+/// The velocity of money over a transaction log: the total value transferred divided by the
+/// average money supply. Only value that changes hands counts as transferred, so change
+/// returned to a spender does not. The supply is averaged over the states that the applied
+/// transactions started from. Rejected transactions are skipped, and a log in which nothing
+/// applies has a velocity of zero.
+pub fn velocity(genesis: &State, txs: &[CashTransaction]) -> f64 {
+    let mut state = genesis.clone();
+    let mut transferred: u128 = 0;
+    let mut supply: u128 = 0;
+    let mut applied: u128 = 0;
+    for t in txs.iter() {
+        let Ok(next_state) = DigitalCashSystem::try_next_state(&state, t) else {
+            continue;
+        };
+        let mut inner = t;
+        while let CashTransaction::ConditionalTransfer { inner: nested, .. } = inner {
+            inner = nested;
+        }
+        if let CashTransaction::Transfer {
+            spends, receives, ..
+        }
+        | CashTransaction::SignedTransfer {
+            spends, receives, ..
+        }
+        | CashTransaction::PooledTransfer { spends, receives } = inner
+        {
+            transferred += receives
+                .iter()
+                .filter(|bill| !spends.iter().any(|spent| spent.owner == bill.owner))
+                .map(|bill| bill.amount as u128)
+                .sum::<u128>();
+        }
+        supply += state.total_supply() as u128;
+        applied += 1;
+        state = next_state;
+    }
+    if applied == 0 || supply == 0 {
+        return 0.0;
+    }
+    transferred as f64 / (supply as f64 / applied as f64)
+}
+
+/// Plan a sequence of transactions that brings each listed user's balance to its target.
+/// Users who are not listed keep their balances. Every user holding more than their target
+/// spends all of their spendable bills, those without a spend condition that have not
+/// expired, keeping what they do not give away as change and paying out the surplus to users
+/// below their target. Any surplus nobody needs is burned. Shares and other bills count
+/// toward a user's balance but are left where they are.
+///
+/// Returns `None` if the plan is infeasible, which is when a user's spendable bills are not
+/// enough to pay their surplus, when the users below their targets need more than the users
+/// above their targets can give, or when a user is listed twice.
+pub fn plan_rebalance(state: &State, target: &[(User, u64)]) -> Option<Vec<CashTransaction>> {
+    let mut donors = Vec::new();
+    let mut deficits = Vec::new();
+    for (i, (user, amount)) in target.iter().enumerate() {
+        if target[..i].iter().any(|(other, _)| other == user) {
+            return None;
+        }
+        let balance = state.balance_of(user);
+        if balance > *amount {
+            let surplus = balance - amount;
+            let spendable = state
+                .owned_bills(user)
+                .iter()
+                .filter(|bill| bill.spend_condition.is_none() && bill.expires_at > state.epoch)
+                .try_fold(0u64, |total, bill| total.checked_add(bill.amount))?;
+            if spendable < surplus {
+                return None;
+            }
+            donors.push((*user, spendable - surplus, surplus));
+        } else if balance < *amount {
+            deficits.push((*user, amount - balance));
+        }
+    }
+    let surplus: u128 = donors.iter().map(|(_, _, surplus)| *surplus as u128).sum();
+    let deficit: u128 = deficits.iter().map(|(_, deficit)| *deficit as u128).sum();
+    if deficit > surplus {
+        return None;
+    }
+
+    let mut plan = Vec::new();
+    let mut state = state.clone();
+    let mut deficits = deficits.into_iter().peekable();
+    for (donor, keep, mut surplus) in donors {
+        let mut receives = Vec::new();
+        let mut serial = state.next_serial;
+        if keep > 0 {
+            receives.push(Bill::new(donor, keep, serial));
+            serial += 1;
+        }
+        while surplus > 0 {
+            let Some((user, needed)) = deficits.peek_mut() else {
+                break;
+            };
+            let paid = (*needed).min(surplus);
+            receives.push(Bill::new(*user, paid, serial));
+            serial += 1;
+            surplus -= paid;
+            *needed -= paid;
+            if *needed == 0 {
+                deficits.next();
+            }
+        }
+
+        let spends: Vec<Bill> = state
+            .owned_bills(&donor)
+            .into_iter()
+            .filter(|bill| bill.spend_condition.is_none() && bill.expires_at > state.epoch)
+            .collect();
+        let t = if receives.is_empty() {
+            CashTransaction::Burn {
+                signer: donor,
+                spends,
+            }
+        } else {
+            CashTransaction::Transfer {
+                spends,
+                receives,
+                memo: Vec::new(),
+            }
+        };
+        state = DigitalCashSystem::try_next_state(&state, &t).ok()?;
+        plan.push(t);
+    }
+    Some(plan)
+}
+
+/// Plan a sequence of transactions that leaves each of the listed users with an equal share of
+/// their combined balance, drawing from whoever holds more than their share. Returns `None` if
+/// no users or the same user twice are listed, or if the total does not divide evenly among
+/// them.
+pub fn equalize_plan(state: &State, among: &[User]) -> Option<Vec<CashTransaction>> {
+    if among.is_empty() {
+        return None;
+    }
+    let total: u128 = among
+        .iter()
+        .map(|user| state.balance_of(user) as u128)
+        .sum();
+    if !total.is_multiple_of(among.len() as u128) {
+        return None;
+    }
+    let share = (total / among.len() as u128) as u64;
+    let target: Vec<(User, u64)> = among.iter().map(|user| (*user, share)).collect();
+    plan_rebalance(state, &target)
+}
+
+/// Reissue every holder's bills as bills worth exactly `unit` each, as a mixing pool with
+/// uniform notes would require. Each holder's bills are merged and split into as many units
+/// as they can afford, and whatever is left over below `unit` is destroyed. Holders who cannot
+/// afford a single unit have their bills burned outright. Returns the resulting state along
+/// with the transactions that produced it. Bills that cannot be spent without a signature
+/// are left as they are.
+///
+/// A transfer may only create [`FANOUT_FACTOR`] bills for every bill it spends, so a holder
+/// owed more units than that is paid over several transfers, each passing the value not yet
+/// reissued on to the next as a single change bill. Fails with the first error any of the
+/// transactions runs into.
+pub fn quantize(state: &State, unit: u64) -> Result<(State, Vec<CashTransaction>), TransferError> {
+    let mut state = state.clone();
+    let mut transactions = Vec::new();
+    if unit == 0 {
+        return Ok((state, transactions));
+    }
+
+    for holder in state.holders() {
+        let mut spends: Vec<Bill> = state
+            .owned_bills(&holder)
+            .into_iter()
+            .filter(|bill| bill.spend_condition.is_none())
+            .collect();
+        if spends.iter().all(|bill| bill.amount == unit) {
+            continue;
+        }
+        let mut total: u64 = spends
+            .iter()
+            .fold(0, |total, bill| total.saturating_add(bill.amount));
+        let mut units = total / unit;
+        if units == 0 {
+            let t = CashTransaction::Burn {
+                signer: holder,
+                spends,
+            };
+            state = DigitalCashSystem::try_next_state(&state, &t)?;
+            transactions.push(t);
+            continue;
+        }
+
+        while !spends.is_empty() {
+            let fanout = (spends.len() as u64).saturating_mul(FANOUT_FACTOR as u64);
+            // leave room for the change bill unless this transfer pays out the last units
+            let paid = if units <= fanout { units } else { fanout - 1 };
+            let mut receives: Vec<Bill> = (0..paid)
+                .map(|i| Bill::new(holder, unit, state.next_serial + i))
+                .collect();
+            let mut change = Vec::new();
+            if paid < units {
+                let amount = total - paid * unit;
+                change.push(Bill::new(holder, amount, state.next_serial + paid));
+                receives.extend(change.iter().cloned());
+                total = amount;
+                units -= paid;
+            }
+            let t = CashTransaction::Transfer {
+                spends,
+                receives,
+                memo: Vec::new(),
+            };
+            state = DigitalCashSystem::try_next_state(&state, &t)?;
+            transactions.push(t);
+            spends = change;
+        }
+    }
+    Ok((state, transactions))
+}
+
+/// A summary of exactly what one transition did to the state
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TransitionReceipt {
+    /// The serials of the bills the transition created, in ascending order
+    pub created: Vec<u64>,
+    /// The serials of the bills the transition destroyed, in ascending order
+    pub destroyed: Vec<u64>,
+    /// The value destroyed by explicit burns and expiry
+    pub burned: u64,
+    /// The fee paid by the transition, as computed by [`fee_for`]
+    pub fee: u64,
+    /// The memo carried by the transition, or an empty memo if it carries none
+    pub memo: Vec<u8>,
+    /// Whether the transition was applied, or why it was rejected
+    pub outcome: Result<(), TransferError>,
+}
+
+/// Apply each transaction in turn, skipping the ones that are rejected, and return the final
+/// state along with the outcome of every transaction in order
+pub fn apply_each(
+    state: &State,
+    txs: &[CashTransaction],
+) -> (State, Vec<Result<(), TransferError>>) {
+    let mut state = state.clone();
+    let mut outcomes = Vec::with_capacity(txs.len());
+    for t in txs.iter() {
+        match DigitalCashSystem::try_next_state(&state, t) {
+            Ok(next_state) => {
+                state = next_state;
+                outcomes.push(Ok(()));
+            }
+            Err(e) => outcomes.push(Err(e)),
+        }
+    }
+    (state, outcomes)
+}
+
+/// Apply a transition and summarize what happened in a receipt. A rejected transition leaves
+/// the state unchanged and produces a receipt with nothing created or destroyed.
+pub fn apply_with_receipt(state: &State, t: &CashTransaction) -> (State, TransitionReceipt) {
+    let next_state = match DigitalCashSystem::try_next_state(state, t) {
+        Ok(next_state) => next_state,
+        Err(e) => {
+            let receipt = TransitionReceipt {
+                created: Vec::new(),
+                destroyed: Vec::new(),
+                burned: 0,
+                fee: 0,
+                memo: Vec::new(),
+                outcome: Err(e),
+            };
+            return (state.clone(), receipt);
+        }
+    };
+
+    let serials = |state: &State| -> Vec<u64> {
+        state
+            .bills_sorted()
+            .iter()
+            .map(|bill| bill.serial)
+            .collect()
+    };
+    let before = serials(state);
+    let after = serials(&next_state);
+    let receipt = TransitionReceipt {
+        created: after
+            .iter()
+            .filter(|serial| !before.contains(serial))
+            .cloned()
+            .collect(),
+        destroyed: before
+            .iter()
+            .filter(|serial| !after.contains(serial))
+            .cloned()
+            .collect(),
+        burned: next_state.total_burned - state.total_burned,
+        fee: fee_for(t),
+        memo: memo_of(t).to_vec(),
+        outcome: Ok(()),
+    };
+    (next_state, receipt)
+}
+
+/// The memo carried by a transaction, looking through conditional transfers
+fn memo_of(t: &CashTransaction) -> &[u8] {
+    match t {
+        CashTransaction::Transfer { memo, .. } => memo,
+        CashTransaction::ConditionalTransfer { inner, .. } => memo_of(inner),
+        _ => &[],
+    }
+}
+
+/// Pick bills owned by the given user worth at least the given amount, taking the largest
+/// bills first so that as few bills as possible are spent. Returns `None` if the user cannot
+/// afford the amount.
+fn select_bills(state: &State, owner: &User, amount: u64) -> Option<Vec<Bill>> {
+    let mut selected = Vec::new();
+    let mut total: u64 = 0;
+    for bill in state.bills_by_amount() {
+        if total >= amount {
+            break;
+        }
+        if bill.owner == *owner {
+            total = total.saturating_add(bill.amount);
+            selected.push(bill);
+        }
+    }
+    if total < amount {
+        return None;
+    }
+    Some(selected)
+}
+
+/// The reasons a planned set of outputs cannot be built into a transfer
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum BuildError {
+    /// There are no outputs to build
+    NoOutputs,
+    /// An output is worth nothing
+    ZeroAmountOutput,
+    /// The outputs add up to more than a `u64` can hold
+    OutputOverflow,
+    /// The outputs are worth more than the inputs they are funded from
+    OverAllocated { available: u64, requested: u64 },
+}
+
+/// Check a planned set of outputs against the total value of the inputs funding them before
+/// building a transfer, returning the change left over for the spender
+pub fn validate_outputs(inputs_total: u64, outputs: &[(User, u64)]) -> Result<u64, BuildError> {
+    if outputs.is_empty() {
+        return Err(BuildError::NoOutputs);
+    }
+    let mut requested: u64 = 0;
+    for (_, amount) in outputs.iter() {
+        if *amount == 0 {
+            return Err(BuildError::ZeroAmountOutput);
+        }
+        requested = requested
+            .checked_add(*amount)
+            .ok_or(BuildError::OutputOverflow)?;
+    }
+    inputs_total
+        .checked_sub(requested)
+        .ok_or(BuildError::OverAllocated {
+            available: inputs_total,
+            requested,
+        })
+}
+
+/// Pay the given amount from one user to another. Bills owned by the payer are selected
+/// automatically and any change is returned to the payer. Returns the resulting state along
+/// with the transfer that produced it, or `None` if the payer cannot afford the payment or the
+/// selected bills add up to more than a `u64` can hold.
+pub fn pay(state: &State, from: User, to: User, amount: u64) -> Option<(State, CashTransaction)> {
+    let spends = select_bills(state, &from, amount)?;
+    let spent = spends
+        .iter()
+        .try_fold(0u64, |total, bill| total.checked_add(bill.amount))?;
+    let change = validate_outputs(spent, &[(to, amount)]).ok()?;
+
+    let mut receives = vec![Bill::new(to, amount, state.next_serial)];
+    if change > 0 {
+        receives.push(Bill::new(from, change, state.next_serial + 1));
+    }
+    let transfer = CashTransaction::Transfer {
+        spends,
+        receives,
+        memo: Vec::new(),
+    };
+    let next_state = DigitalCashSystem::try_next_state(state, &transfer).ok()?;
+    Some((next_state, transfer))
+}
+
+/// Build a transaction that destroys exactly the given amount of the owner's money. Bills
+/// are selected largest first, as few as possible. When they add up to exactly the amount
+/// they are burned outright; otherwise they are burned partially, with the excess returned
+/// to the owner as change. Returns `None` if the owner cannot afford the amount, the amount
+/// is zero, or the selected bills add up to more than a `u64` can hold.
+pub fn burn_amount(state: &State, owner: &User, amount: u64) -> Option<CashTransaction> {
+    if amount == 0 {
+        return None;
+    }
+    let spends = select_bills(state, owner, amount)?;
+    let spent = spends
+        .iter()
+        .try_fold(0u64, |total, bill| total.checked_add(bill.amount))?;
+    if spent == amount {
+        return Some(CashTransaction::Burn {
+            signer: *owner,
+            spends,
+        });
+    }
+    Some(CashTransaction::PartialBurn {
+        signer: *owner,
+        spends,
+        change: Bill::new(*owner, spent - amount, state.next_serial),
+    })
+}
+
+/// The most bills of a single owner that [`State::all_change_combinations`] considers
+pub const MAX_COMBINATION_BILLS: usize = 16;
+
+/// The most bills that a single planned transfer spends
+pub const MAX_INPUTS: usize = 4;
+
+/// How many separate transfers, each spending at most [`MAX_INPUTS`] bills, it takes to move
+/// the given amount from one user to another. Bills are spent largest first so that as few
+/// as possible are needed. Returns `None` if the sender cannot afford the amount.
+pub fn transfers_needed(state: &State, from: &User, amount: u64, to: &User) -> Option<usize> {
+    let spends = select_bills(state, from, amount)?;
+    if from == to {
+        return Some(0);
+    }
+    Some(spends.len().div_ceil(MAX_INPUTS))
+}
+
+/// A source of randomness for generating transactions. This crate has no dependencies, so
+/// rather than pulling in a full random number library we only ask for raw 64 bit values.
+pub trait Rng {
+    fn next_u64(&mut self) -> u64;
+
+    /// A value in the range `0..bound`. The bound must not be zero.
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+
+    /// Flip a coin that lands heads with the given probability
+    fn chance(&mut self, probability: f64) -> bool {
+        (self.next_u64() as f64 / u64::MAX as f64) < probability
+    }
+}
+
+/// A small, fast, seedable xorshift generator. Not remotely cryptographic, but plenty for
+/// producing reproducible test inputs.
+pub struct XorShiftRng(u64);
+
+impl XorShiftRng {
+    pub fn seed_from_u64(seed: u64) -> Self {
+        // xorshift gets stuck on zero, so nudge the seed away from it
+        XorShiftRng(seed ^ 0x9E37_79B9_7F4A_7C15)
+    }
+}
+
+impl Rng for XorShiftRng {
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+}
+
+/// Pick one of the users uniformly at random
+fn arbitrary_user<R: Rng>(rng: &mut R) -> User {
+    match rng.below(3) {
+        0 => User::Alice,
+        1 => User::Bob,
+        _ => User::Charlie,
+    }
+}
+
+/// Generate a transaction against the given state. With probability `invalid_rate` the
+/// transaction is deliberately invalid (a double spend, a bad serial, or an over spend);
+/// otherwise it is a valid mint or transfer.
+pub fn arbitrary_tx<R: Rng>(state: &State, rng: &mut R, invalid_rate: f64) -> CashTransaction {
+    arbitrary_labelled_tx(state, rng, invalid_rate).0
+}
+
+/// Like [`arbitrary_tx`] but also reports whether the transaction was generated to be valid
+fn arbitrary_labelled_tx<R: Rng>(
+    state: &State,
+    rng: &mut R,
+    invalid_rate: f64,
+) -> (CashTransaction, bool) {
+    let bills = state.bills_sorted();
+    let next_serial = state.next_serial;
+
+    if rng.chance(invalid_rate) {
+        let Some(&bill) = bills.get(rng.below(bills.len().max(1) as u64) as usize) else {
+            let transfer = CashTransaction::Transfer {
+                spends: vec![],
+                receives: vec![Bill::new(arbitrary_user(rng), 1, next_serial)],
+                memo: Vec::new(),
+            };
+            return (transfer, false);
+        };
+        let mut kind = rng.below(3);
+        if kind == 2 && bill.amount == u64::MAX {
+            // there is no way to over spend a maximal bill, so double spend it instead
+            kind = 0;
+        }
+        let transfer = match kind {
+            // spend the same bill twice
+            0 => CashTransaction::Transfer {
+                spends: vec![bill.clone(), bill.clone()],
+                receives: vec![Bill::new(arbitrary_user(rng), 1, next_serial)],
+                memo: Vec::new(),
+            },
+            // skip a serial
+            1 => CashTransaction::Transfer {
+                spends: vec![bill.clone()],
+                receives: vec![Bill::new(arbitrary_user(rng), bill.amount, next_serial + 1)],
+                memo: Vec::new(),
+            },
+            // receive more than is spent
+            _ => CashTransaction::Transfer {
+                spends: vec![bill.clone()],
+                receives: vec![Bill::new(arbitrary_user(rng), bill.amount + 1, next_serial)],
+                memo: Vec::new(),
+            },
+        };
+        return (transfer, false);
+    }
+
+    if bills.is_empty() || rng.chance(0.2) {
+        let minter = arbitrary_user(rng);
+        if state.mints_this_epoch.get(&minter).copied().unwrap_or(0) >= MAX_MINTS_PER_EPOCH {
+            // the minter is rate limited, so move on to the next epoch instead
+            return (CashTransaction::Tick, true);
+        }
+        let mint = CashTransaction::Mint {
+            minter,
+            amount: 1 + rng.below(1000),
+        };
+        return (mint, true);
+    }
+
+    // spend a few distinct bills
+    let mut spends: Vec<Bill> = Vec::new();
+    for _ in 0..1 + rng.below(3) {
+        let bill = bills[rng.below(bills.len() as u64) as usize];
+        if !spends.contains(bill) {
+            spends.push(bill.clone());
+        }
+    }
+    let total: u64 = spends.iter().map(|bill| bill.amount).sum();
+
+    // burn a little, then split the rest into a few nonzero outputs
+    let mut remaining = total - rng.below(total / 10 + 1);
+    let outputs = (1 + rng.below(3)).min(remaining);
+    let mut receives = Vec::new();
+    for i in 0..outputs {
+        let amount = if i + 1 == outputs {
+            remaining
+        } else {
+            1 + rng.below(remaining - (outputs - i - 1))
+        };
+        remaining -= amount;
+        receives.push(Bill::new(arbitrary_user(rng), amount, next_serial + i));
+    }
+    if receives.is_empty() {
+        // everything was burned
+        let burn = CashTransaction::Burn {
+            signer: spends[0].owner,
+            spends,
+        };
+        return (burn, true);
+    }
+    let transfer = CashTransaction::Transfer {
+        spends,
+        receives,
+        memo: Vec::new(),
+    };
+    (transfer, true)
+}
+
+#[test]
+fn sm_5_mint_new_cash() {
+    let start = State::new();
+    let end = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::Mint {
+            minter: User::Alice,
+            amount: 20,
+        },
+    );
+
+    let mut expected = State::from([Bill::new(User::Alice, 20, 0)]);
+    expected.mints_this_epoch.insert(User::Alice, 1);
+    assert_eq!(end, expected);
+}
+
+#[test]
+fn sm_5_airdrop_to_three_recipients() {
+    let mut start = State::from([Bill::new(User::Alice, 5, 0)]);
+    start.set_serial(3).unwrap();
+    let end = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::Airdrop {
+            signer: User::Alice,
+            recipients: vec![(User::Alice, 10), (User::Bob, 20), (User::Charlie, 30)],
+        },
+    );
+
+    let mut expected = State::from([
+        Bill::new(User::Alice, 5, 0),
+        Bill::new(User::Alice, 10, 3),
+        Bill::new(User::Bob, 20, 4),
+        Bill::new(User::Charlie, 30, 5),
+    ]);
+    expected.set_serial(6).unwrap();
+    assert_eq!(end, expected);
+    assert_eq!(end.balance_of(&User::Alice), 15);
+    assert_eq!(end.balance_of(&User::Bob), 20);
+    assert_eq!(end.balance_of(&User::Charlie), 30);
+    assert_eq!(end.next_serial(), 6);
+}
+
+#[test]
+fn sm_5_airdrop_skips_zero_amounts() {
+    let start = State::new();
+    let end = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::Airdrop {
+            signer: User::Alice,
+            recipients: vec![(User::Alice, 10), (User::Bob, 0), (User::Charlie, 30)],
+        },
+    );
+
+    let expected = State::from([
+        Bill::new(User::Alice, 10, 0),
+        Bill::new(User::Charlie, 30, 1),
+    ]);
+    assert_eq!(end, expected);
+}
+
+#[test]
+fn sm_5_airdrop_overflowing_total_supply_fails() {
+    let start = State::from([Bill::new(User::Alice, u64::MAX - 10, 0)]);
+    let end = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::Airdrop {
+            signer: User::Alice,
+            recipients: vec![(User::Bob, 5), (User::Charlie, 6)],
+        },
+    );
+
+    assert_eq!(end, start);
+}
+
+#[test]
+fn sm_5_transfer_records_parents() {
+    let start = State::from([
+        Bill::new(User::Alice, 20, 0),
+        Bill::new(User::Bob, 30, 1),
+        Bill::new(User::Charlie, 5, 2),
+    ]);
+    let end = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 30, 1)],
+            receives: vec![
+                Bill::new(User::Charlie, 45, 3),
+                Bill::new(User::Alice, 5, 4),
+            ],
+            memo: Vec::new(),
+        },
+    );
+
+    assert_eq!(end.provenance(3), vec![0, 1]);
+    assert_eq!(end.provenance(4), vec![0, 1]);
+    assert_eq!(end.provenance(2), Vec::<u64>::new());
+    assert_eq!(end.provenance(0), Vec::<u64>::new());
+}
+
+#[test]
+fn sm_5_gini_of_empty_state_is_none() {
+    assert_eq!(State::new().gini(), None);
+}
+
+#[test]
+fn sm_5_gini_of_equal_distribution_is_zero() {
+    let state = State::from([
+        Bill::new(User::Alice, 30, 0),
+        Bill::new(User::Bob, 10, 1),
+        Bill::new(User::Bob, 20, 2),
+        Bill::new(User::Charlie, 30, 3),
+    ]);
+    let gini = state.gini().unwrap();
+    assert!(gini.abs() < 1e-9);
+}
+
+#[test]
+fn sm_5_gini_of_single_holder_is_maximal() {
+    // The theoretical maximum for three users is (n - 1) / n
+    let state = State::from([Bill::new(User::Alice, 100, 0)]);
+    let gini = state.gini().unwrap();
+    assert!((gini - 2.0 / 3.0).abs() < 1e-9);
+
+    // a whale next to two near-empty holders comes close to it
+    let state = State::from([
+        Bill::new(User::Alice, 1_000_000, 0),
+        Bill::new(User::Bob, 1, 1),
+        Bill::new(User::Charlie, 1, 2),
+    ]);
+    let gini = state.gini().unwrap();
+    assert!((gini - 2.0 / 3.0).abs() < 1e-5);
+}
+
+#[test]
+fn sm_5_gini_counts_shares_and_large_bills() {
+    let state = State::from([
+        Bill::new(User::Alice, u64::MAX, 0),
+        Bill::new(User::Alice, u64::MAX, 1),
+    ]);
+    let gini = state.gini().unwrap();
+    assert!((gini - 2.0 / 3.0).abs() < 1e-9);
+
+    let start = State::from([
+        Bill::new(User::Alice, 30, 0),
+        Bill::new(User::Bob, 30, 1),
+        Bill::new(User::Charlie, 60, 2),
+    ]);
+    let shared = DigitalCashSystem::try_next_state(
+        &start,
+        &CashTransaction::ShareBills {
+            spends: vec![Bill::new(User::Alice, 30, 0), Bill::new(User::Bob, 30, 1)],
+            shares: vec![(User::Alice, 30), (User::Bob, 30)],
+        },
+    )
+    .unwrap();
+    assert!(shared.gini().unwrap() > 0.0);
+    assert_eq!(shared.gini(), Some(start.gini().unwrap()));
+}
+
+#[test]
+fn sm_5_validation_cache_reuses_results() {
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
+    let transfer = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![Bill::new(User::Bob, 20, 1)],
+        memo: Vec::new(),
+    };
+    let mut cache = ValidationCache::new();
+
+    assert_eq!(cache.validate(&start, &transfer), Ok(()));
+    assert_eq!(cache.validate(&start, &transfer), Ok(()));
+    assert_eq!(cache.validations(), 1);
+
+    // Once the transfer is applied, the same transfer is no longer valid
+    let end = DigitalCashSystem::next_state(&start, &transfer);
+    assert_eq!(
+        cache.validate(&end, &transfer),
+        Err(TransferError::NonContiguousSerial {
+            expected: 2,
+            got: 1
+        })
+    );
+    assert_eq!(cache.validations(), 2);
+}
+
+#[test]
+fn sm_5_validation_cache_sees_pausing() {
+    let mut start = State::from([Bill::new(User::Alice, 20, 0)]);
+    start.set_pause_authority(User::Charlie);
+    let mint = CashTransaction::Mint {
+        minter: User::Alice,
+        amount: 10,
+    };
+    let mut cache = ValidationCache::new();
+    assert_eq!(cache.validate(&start, &mint), Ok(()));
+
+    // pausing changes no bills, but the cached outcome no longer applies
+    let paused = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::SetPaused {
+            value: true,
+            authority: User::Charlie,
+        },
+    );
+    assert_ne!(paused.state_root(), start.state_root());
+    assert_eq!(
+        cache.validate(&paused, &mint),
+        Err(TransferError::SystemPaused)
+    );
+    assert_eq!(cache.validations(), 2);
+}
+
+#[test]
+fn sm_5_bytes_round_trip() {
+    let mut state = State::from([
+        Bill::new(User::Charlie, 7, 4),
+        Bill::new(User::Alice, 20, 0),
+        Bill::new(User::Bob, 13, 2),
+    ]);
+    state.set_serial(5).unwrap();
+
+    let bytes = state.to_bytes();
+    assert_eq!(bytes.len(), 8 + 3 * 17 + 8);
+    assert_eq!(State::from_bytes(&bytes), Ok(state.clone()));
+
+    // The encoding does not depend on how the state was built
+    let mut rebuilt = State::from([
+        Bill::new(User::Bob, 13, 2),
+        Bill::new(User::Alice, 20, 0),
+        Bill::new(User::Charlie, 7, 4),
+    ]);
+    rebuilt.set_serial(5).unwrap();
+    assert_eq!(rebuilt.to_bytes(), bytes);
+}
+
+#[test]
+fn sm_5_bytes_truncated_input_fails() {
+    let state = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 13, 1)]);
+    let bytes = state.to_bytes();
+
+    for len in 0..bytes.len() {
+        assert_eq!(
+            State::from_bytes(&bytes[..len]),
+            Err(DecodeError::UnexpectedEnd)
+        );
+    }
+}
+
+#[test]
+fn sm_5_shard_partitions_bills_by_serial() {
+    let state = State::from([
+        Bill::new(User::Alice, 10, 0),
+        Bill::new(User::Bob, 20, 1),
+        Bill::new(User::Charlie, 30, 2),
+        Bill::new(User::Alice, 40, 3),
+        Bill::new(User::Bob, 50, 4),
+        Bill::new(User::Charlie, 60, 5),
+        Bill::new(User::Alice, 70, 6),
+    ]);
+    let shards = state.shard(3);
+    assert_eq!(shards.len(), 3);
+
+    let mut union = HashSet::new();
+    for (i, shard) in shards.iter().enumerate() {
+        assert_eq!(shard.next_serial(), state.next_serial());
+        for bill in shard.bills.iter() {
+            assert_eq!(bill.serial % 3, i as u64);
+            assert!(union.insert(bill.clone()));
+        }
+    }
+    assert_eq!(union, state.bills);
+}
+
+#[test]
+fn sm_5_conditional_transfer_applies_when_condition_holds() {
+    let start = State::from([Bill::new(User::Alice, 30, 0), Bill::new(User::Alice, 20, 1)]);
+    let end = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::ConditionalTransfer {
+            condition: Condition::MinBalance(User::Alice, 50),
+            inner: Box::new(CashTransaction::Transfer {
+                spends: vec![Bill::new(User::Alice, 30, 0)],
+                receives: vec![Bill::new(User::Bob, 30, 2)],
+                memo: Vec::new(),
+            }),
+        },
+    );
+
+    let mut expected = State::from([Bill::new(User::Alice, 20, 1), Bill::new(User::Bob, 30, 2)]);
+    expected.set_serial(3).unwrap();
+    assert_eq!(end, expected);
+}
+
+#[test]
+fn sm_5_conditional_transfer_fails_when_condition_does_not_hold() {
+    let start = State::from([Bill::new(User::Alice, 30, 0), Bill::new(User::Alice, 19, 1)]);
+    let transfer = CashTransaction::ConditionalTransfer {
+        condition: Condition::MinBalance(User::Alice, 50),
+        inner: Box::new(CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 30, 0)],
+            receives: vec![Bill::new(User::Bob, 30, 2)],
+            memo: Vec::new(),
+        }),
+    };
+
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&start, &transfer),
+        Err(TransferError::ConditionNotMet)
+    );
+    assert_eq!(DigitalCashSystem::next_state(&start, &transfer), start);
+}
+
+#[test]
+fn sm_5_touched_serials_of_transfer_are_its_spends() {
+    let transfer = CashTransaction::Transfer {
+        spends: vec![
+            Bill::new(User::Alice, 10, 3),
+            Bill::new(User::Bob, 20, 7),
+            Bill::new(User::Alice, 5, 1),
+        ],
+        receives: vec![Bill::new(User::Charlie, 35, 8)],
+        memo: Vec::new(),
+    };
+    assert_eq!(touched_serials(&transfer), vec![3, 7, 1]);
+}
+
+#[test]
+fn sm_5_touched_serials_of_mint_is_empty() {
+    let mint = CashTransaction::Mint {
+        minter: User::Alice,
+        amount: 20,
+    };
+    assert!(touched_serials(&mint).is_empty());
+}
+
+#[test]
+fn sm_5_arbitrary_transactions_never_corrupt_state() {
+    let mut rng = XorShiftRng::seed_from_u64(42);
+    let mut state = State::new();
+    let mut rejected = 0;
+
+    for _ in 0..2000 {
+        let (t, valid) = arbitrary_labelled_tx(&state, &mut rng, 0.3);
+        let result = DigitalCashSystem::try_next_state(&state, &t);
+        assert_eq!(result.is_ok(), valid);
+
+        let next = DigitalCashSystem::next_state(&state, &t);
+        match result {
+            Ok(expected) => assert_eq!(next, expected),
+            Err(_) => {
+                assert_eq!(next, state);
+                rejected += 1;
+            }
+        }
+        if let CashTransaction::Transfer { .. } | CashTransaction::Burn { .. } = t {
+            assert!(next.total_supply() <= state.total_supply());
+        }
+        let mut serials = HashSet::new();
+        for bill in next.bills.iter() {
+            assert!(bill.amount > 0);
+            assert!(bill.serial < next.next_serial);
+            assert!(serials.insert(bill.serial));
+        }
+        state = next;
+    }
+    assert!(rejected > 0);
+}
+
+#[test]
+fn sm_5_ordered_state_iterates_in_serial_order() {
+    let state = OrderedState::from(State::from([
+        Bill::new(User::Charlie, 5, 3),
+        Bill::new(User::Alice, 20, 0),
+        Bill::new(User::Bob, 1, 2),
+        Bill::new(User::Alice, 7, 1),
+    ]));
+    let serials: Vec<u64> = state.iter().map(|bill| bill.serial).collect();
+    assert_eq!(serials, vec![0, 1, 2, 3]);
+}
+
+#[test]
+fn sm_5_ordered_state_matches_state() {
+    let start = State::from([Bill::new(User::Alice, 42, 0), Bill::new(User::Bob, 8, 1)]);
+    let transactions = [
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 42, 0)],
+            receives: vec![
+                Bill::new(User::Charlie, 30, 2),
+                Bill::new(User::Alice, 12, 3),
+            ],
+            memo: Vec::new(),
+        },
+        CashTransaction::Mint {
+            minter: User::Bob,
+            amount: 5,
+        },
+        // rejected: the bill was already spent
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 42, 0)],
+            receives: vec![Bill::new(User::Bob, 42, 5)],
+            memo: Vec::new(),
+        },
+    ];
+
+    let mut state = start.clone();
+    let mut ordered = OrderedState::from(start);
+    for t in transactions.iter() {
+        state = DigitalCashSystem::next_state(&state, t);
+        ordered = OrderedDigitalCashSystem::next_state(&ordered, t);
+        assert_eq!(State::from(ordered.clone()), state);
+    }
+    assert_eq!(ordered.next_serial(), 5);
+}
+
+#[test]
+fn sm_5_fragmentation_compares_holdings() {
+    let mut bills = vec![Bill::new(User::Alice, 100, 0)];
+    for serial in 1..=100 {
+        bills.push(Bill::new(User::Bob, 1, serial));
+    }
+    let state = State::from_iter(bills);
+
+    assert_eq!(state.balance_of(&User::Alice), state.balance_of(&User::Bob));
+    assert_eq!(state.fragmentation(&User::Alice), Some(0.01));
+    assert_eq!(state.fragmentation(&User::Bob), Some(1.0));
+    assert_eq!(state.fragmentation(&User::Charlie), None);
+}
+
+#[test]
+fn sm_5_weight_depends_on_inputs_and_outputs() {
+    let mint = CashTransaction::Mint {
+        minter: User::Alice,
+        amount: 20,
+    };
+    let transfer = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 10, 0), Bill::new(User::Alice, 10, 1)],
+        receives: vec![Bill::new(User::Bob, 20, 2)],
+        memo: Vec::new(),
+    };
+    assert_eq!(weight(&mint), MINT_WEIGHT);
+    assert_eq!(weight(&transfer), 2 * INPUT_WEIGHT + OUTPUT_WEIGHT);
+}
+
+#[test]
+fn sm_5_pack_block_respects_weight_and_conflicts() {
+    let state = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 10, 1)]);
+    let pool = vec![
+        // 0: Alice pays Bob
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Bob, 20, 2)],
+            memo: Vec::new(),
+        },
+        // 1: conflicts with 0 by spending the same bill
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Charlie, 20, 3)],
+            memo: Vec::new(),
+        },
+        // 2: too heavy for what is left of the budget
+        CashTransaction::Airdrop {
+            signer: User::Alice,
+            recipients: vec![(User::Alice, 1), (User::Bob, 1), (User::Charlie, 1)],
+        },
+        // 3: fits
+        CashTransaction::Mint {
+            minter: User::Charlie,
+            amount: 5,
+        },
+    ];
+    let max_weight = 20;
+    let block = pack_block(&state, &pool, max_weight);
+    assert_eq!(block, vec![0, 3]);
+
+    let total_weight: u64 = block.iter().map(|&i| weight(&pool[i])).sum();
+    assert!(total_weight <= max_weight);
+    for &i in block.iter() {
+        for &j in block.iter().filter(|&&j| j > i) {
+            let spent_i = touched_serials(&pool[i]);
+            assert!(touched_serials(&pool[j])
+                .iter()
+                .all(|serial| !spent_i.contains(serial)));
+        }
+    }
+}
+
+#[test]
+fn sm_5_valid_genesis_passes() {
+    let bills = [
+        Bill::new(User::Bob, 5, 1),
+        Bill::new(User::Alice, 20, 0),
+        Bill::new(User::Charlie, 7, 2),
+    ];
+    assert_eq!(validate_genesis(&bills), Ok(()));
+}
+
+#[test]
+fn sm_5_genesis_with_serial_gap_fails() {
+    let bills = [
+        Bill::new(User::Alice, 20, 0),
+        Bill::new(User::Bob, 5, 1),
+        Bill::new(User::Charlie, 7, 3),
+    ];
+    assert_eq!(
+        validate_genesis(&bills),
+        Err(GenesisError::SerialGap {
+            expected: 2,
+            got: 3
+        })
+    );
+}
+
+#[test]
+fn sm_5_genesis_with_duplicate_serial_fails() {
+    let bills = [
+        Bill::new(User::Alice, 20, 0),
+        Bill::new(User::Bob, 5, 1),
+        Bill::new(User::Charlie, 7, 1),
+    ];
+    assert_eq!(
+        validate_genesis(&bills),
+        Err(GenesisError::DuplicateSerial(1))
+    );
+}
+
+#[test]
+fn sm_5_observer_counts_outcomes() {
+    #[derive(Default)]
+    struct Counter {
+        accepted: usize,
+        rejected: usize,
+    }
+
+    impl CashObserver for Counter {
+        fn on_transition(
+            &mut self,
+            _before: &State,
+            _t: &CashTransaction,
+            result: &Result<State, TransferError>,
+        ) {
+            match result {
+                Ok(_) => self.accepted += 1,
+                Err(_) => self.rejected += 1,
+            }
+        }
+    }
+
+    let transactions = [
+        CashTransaction::Mint {
+            minter: User::Alice,
+            amount: 20,
+        },
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Bob, 20, 1)],
+            memo: Vec::new(),
+        },
+        // rejected: double spend
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Bob, 20, 2)],
+            memo: Vec::new(),
+        },
+        // rejected: nothing spent
+        CashTransaction::Transfer {
+            spends: vec![],
+            receives: vec![Bill::new(User::Bob, 20, 2)],
+            memo: Vec::new(),
+        },
+    ];
+
+    let mut counter = Counter::default();
+    let mut state = State::new();
+    for t in transactions.iter() {
+        state = DigitalCashSystem::next_state_observed(&state, t, &mut counter);
+    }
+    assert_eq!(counter.accepted, 2);
+    assert_eq!(counter.rejected, 2);
+    let mut expected = State::from([Bill::new(User::Bob, 20, 1)]);
+    expected.set_serial(2).unwrap();
+    expected.mints_this_epoch.insert(User::Alice, 1);
+    assert_eq!(state, expected);
+}
+
+#[test]
+fn sm_5_tick_past_expiry_removes_bill() {
+    let start = State::from([
+        Bill::new(User::Alice, 20, 0).expiring_at(2),
+        Bill::new(User::Bob, 5, 1),
+    ]);
+
+    let (once, events) =
+        DigitalCashSystem::apply_with_events(&start, &CashTransaction::Tick).unwrap();
+    assert_eq!(once.epoch(), 1);
+    assert_eq!(once.bills, start.bills);
+    assert!(events.is_empty());
+
+    let (twice, events) =
+        DigitalCashSystem::apply_with_events(&once, &CashTransaction::Tick).unwrap();
+    assert_eq!(twice.epoch(), 2);
+    assert_eq!(twice.bills, HashSet::from([Bill::new(User::Bob, 5, 1)]));
+    assert_eq!(twice.total_burned(), 20);
+    assert_eq!(
+        events,
+        vec![CashEvent::BillExpired {
+            serial: 0,
+            amount: 20
+        }]
+    );
+}
+
+#[test]
+fn sm_5_spending_expired_bill_fails() {
+    let mut start = State::from([Bill::new(User::Alice, 20, 0).expiring_at(3)]);
+    // a state that reached the expiry epoch without the tick clearing the bill out
+    start.epoch = 3;
+    let transfer = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![Bill::new(User::Bob, 20, 1)],
+        memo: Vec::new(),
+    };
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&start, &transfer),
+        Err(TransferError::ExpiredBill { serial: 0 })
+    );
+
+    // once ticked past its expiry the bill is simply gone
+    let mut start = State::from([Bill::new(User::Alice, 20, 0).expiring_at(1)]);
+    start = DigitalCashSystem::next_state(&start, &CashTransaction::Tick);
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&start, &transfer),
+        Err(TransferError::NonexistentBill { serial: 0 })
+    );
+}
+
+#[test]
+fn sm_5_net_flow_over_history() {
+    let genesis = State::from([Bill::new(User::Alice, 100, 0), Bill::new(User::Bob, 50, 1)]);
+    let txs = [
+        // Alice pays Bob 60
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 100, 0)],
+            receives: vec![Bill::new(User::Bob, 60, 2), Bill::new(User::Alice, 40, 3)],
+            memo: Vec::new(),
+        },
+        // Bob pays Alice 20
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Bob, 50, 1)],
+            receives: vec![Bill::new(User::Alice, 20, 4), Bill::new(User::Bob, 30, 5)],
+            memo: Vec::new(),
+        },
+        // rejected: the bill was already spent
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 100, 0)],
+            receives: vec![Bill::new(User::Bob, 100, 6)],
+            memo: Vec::new(),
+        },
+        // Alice pays Charlie, which does not involve Bob
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 40, 3)],
+            receives: vec![Bill::new(User::Charlie, 40, 6)],
+            memo: Vec::new(),
+        },
+    ];
+
+    assert_eq!(net_flow(&genesis, &txs, &User::Alice, &User::Bob), 40);
+    assert_eq!(net_flow(&genesis, &txs, &User::Bob, &User::Alice), -40);
+    assert_eq!(net_flow(&genesis, &txs, &User::Alice, &User::Charlie), 40);
+}
+
+#[test]
+fn sm_5_transfers_blocked_while_paused() {
+    let mut start = State::from([Bill::new(User::Alice, 20, 0)]);
+    start.set_pause_authority(User::Charlie);
+    let transfer = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![Bill::new(User::Bob, 20, 1)],
+        memo: Vec::new(),
+    };
+    let mint = CashTransaction::Mint {
+        minter: User::Bob,
+        amount: 5,
+    };
+
+    let paused = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::SetPaused {
+            value: true,
+            authority: User::Charlie,
+        },
+    );
+    assert!(paused.is_paused());
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&paused, &transfer),
+        Err(TransferError::SystemPaused)
+    );
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&paused, &mint),
+        Err(TransferError::SystemPaused)
+    );
+
+    let resumed = DigitalCashSystem::next_state(
+        &paused,
+        &CashTransaction::SetPaused {
+            value: false,
+            authority: User::Charlie,
+        },
+    );
+    assert!(!resumed.is_paused());
+    let end = DigitalCashSystem::next_state(&resumed, &transfer);
+    assert_eq!(end.balance_of(&User::Bob), 20);
+}
+
+#[test]
+fn sm_5_only_authority_can_pause() {
+    let mut start = State::from([Bill::new(User::Alice, 20, 0)]);
+    start.set_pause_authority(User::Charlie);
+    assert_eq!(
+        DigitalCashSystem::try_next_state(
+            &start,
+            &CashTransaction::SetPaused {
+                value: true,
+                authority: User::Alice,
+            },
+        ),
+        Err(TransferError::Unauthorized)
+    );
+}
+
+#[test]
+fn sm_5_plan_rebalance_reaches_target() {
+    let start = State::from([Bill::new(User::Alice, 70, 0), Bill::new(User::Alice, 30, 1)]);
+    let target = [(User::Alice, 40), (User::Bob, 60)];
+    let plan = plan_rebalance(&start, &target).unwrap();
+
+    let mut end = start;
+    for t in plan.iter() {
+        end = DigitalCashSystem::try_next_state(&end, t).unwrap();
+    }
+    assert_eq!(end.balance_of(&User::Alice), 40);
+    assert_eq!(end.balance_of(&User::Bob), 60);
+}
+
+#[test]
+fn sm_5_plan_rebalance_leaves_shares_in_place() {
+    let start = State::from([
+        Bill::new(User::Alice, 10, 0),
+        Bill::new(User::Bob, 5, 1),
+        Bill::new(User::Charlie, 10, 2),
+    ]);
+    let shared = DigitalCashSystem::try_next_state(
+        &start,
+        &CashTransaction::ShareBills {
+            spends: vec![Bill::new(User::Charlie, 10, 2)],
+            shares: vec![(User::Alice, 5), (User::Charlie, 5)],
+        },
+    )
+    .unwrap();
+    let target = [(User::Alice, 12), (User::Bob, 8)];
+    let plan = plan_rebalance(&shared, &target).unwrap();
+
+    let mut end = shared;
+    for t in plan.iter() {
+        end = DigitalCashSystem::try_next_state(&end, t).unwrap();
+    }
+    assert_eq!(end.balance_of(&User::Alice), 12);
+    assert_eq!(end.balance_of(&User::Bob), 8);
+    assert_eq!(end.shared_bills.len(), 1);
+
+    // the share cannot be paid out, so the owned bills alone must cover the surplus
+    let target = [(User::Alice, 4), (User::Bob, 16)];
+    assert!(plan_rebalance(&end, &target).is_none());
+}
+
+#[test]
+fn sm_5_plan_rebalance_beyond_supply_is_infeasible() {
+    let start = State::from([Bill::new(User::Alice, 100, 0)]);
+    let target = [(User::Alice, 40), (User::Bob, 61)];
+    assert!(plan_rebalance(&start, &target).is_none());
+}
+
+#[test]
+fn sm_5_overflow_mode_decides_overflowing_transfer() {
+    let start = State::from([
+        Bill::new(User::Alice, u64::MAX, 0),
+        Bill::new(User::Bob, 10, 1),
+    ]);
+    // no new bill may be large enough to overflow, so overflow the inputs instead
+    let transfer = CashTransaction::Transfer {
+        spends: vec![
+            Bill::new(User::Alice, u64::MAX, 0),
+            Bill::new(User::Bob, 10, 1),
+        ],
+        receives: vec![
+            Bill::new(User::Charlie, MAX_BILL_AMOUNT, 2),
+            Bill::new(User::Alice, 10, 3),
+        ],
+        memo: Vec::new(),
+    };
+
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&start, &transfer),
+        Err(TransferError::InputOverflow)
+    );
+    assert_eq!(DigitalCashSystem::next_state(&start, &transfer), start);
+
+    let end = SaturatingDigitalCashSystem::next_state(&start, &transfer);
+    let expected = State::from([
+        Bill::new(User::Charlie, MAX_BILL_AMOUNT, 2),
+        Bill::new(User::Alice, 10, 3),
+    ]);
+    assert_eq!(end.bills, expected.bills);
+    assert_eq!(end.next_serial(), 4);
+}
+
+#[test]
+fn sm_5_bills_by_amount_largest_first() {
+    let state = State::from([
+        Bill::new(User::Alice, 5, 0),
+        Bill::new(User::Bob, 40, 1),
+        Bill::new(User::Charlie, 12, 2),
+        Bill::new(User::Alice, 40, 3),
+        Bill::new(User::Bob, 1, 4),
+    ]);
+    let serials: Vec<u64> = state
+        .bills_by_amount()
+        .iter()
+        .map(|bill| bill.serial)
+        .collect();
+    assert_eq!(serials, vec![1, 3, 2, 0, 4]);
+}
+
+#[test]
+fn sm_5_pack_block_prefers_higher_fee_per_weight() {
+    let state = State::from([Bill::new(User::Alice, 100, 0), Bill::new(User::Bob, 100, 1)]);
+    let pool = vec![
+        // large, pays a fee of 1
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 100, 0)],
+            receives: vec![
+                Bill::new(User::Bob, 33, 2),
+                Bill::new(User::Charlie, 33, 3),
+                Bill::new(User::Alice, 33, 4),
+            ],
+            memo: Vec::new(),
+        },
+        // small, pays a fee of 10
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Bob, 100, 1)],
+            receives: vec![Bill::new(User::Charlie, 90, 2)],
+            memo: Vec::new(),
+        },
+    ];
+    assert_eq!(fee_for(&pool[0]), 1);
+    assert_eq!(fee_for(&pool[1]), 10);
+
+    // either transaction fits on its own, but not both
+    let max_weight = weight(&pool[0]);
+    assert!(weight(&pool[1]) <= max_weight);
+    assert_eq!(pack_block(&state, &pool, max_weight), vec![1]);
+}
+
+#[test]
+fn sm_5_pack_block_orders_weightless_transactions_last() {
+    let state = State::from([Bill::new(User::Alice, 100, 0), Bill::new(User::Bob, 100, 1)]);
+    let empty = || CashTransaction::Transfer {
+        spends: Vec::new(),
+        receives: Vec::new(),
+        memo: Vec::new(),
+    };
+    let mut pool = vec![empty()];
+    for _ in 0..2 {
+        pool.push(CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 100, 0)],
+            receives: vec![Bill::new(User::Bob, 90, 2)],
+            memo: Vec::new(),
+        });
+        pool.push(empty());
+    }
+    pool.push(CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Bob, 100, 1)],
+        receives: vec![Bill::new(User::Charlie, 100, 3)],
+        memo: Vec::new(),
+    });
+    assert_eq!(weight(&pool[0]), 0);
+
+    // the equal fee rate tie goes to the earlier transaction, and the weightless ones are
+    // only tried afterwards, where they are rejected as empty
+    assert_eq!(pack_block(&state, &pool, 100), vec![1, 5]);
+}
+
+#[test]
+fn sm_5_unspendable_value_sums_burn_addresses() {
+    let state = State::from([
+        Bill::new(User::Alice, 20, 0),
+        Bill::new(User::Charlie, 7, 1),
+        Bill::new(User::Bob, 5, 2),
+        Bill::new(User::Charlie, 3, 3),
+    ]);
+    let burn_addresses = HashSet::from([User::Charlie]);
+    assert_eq!(state.unspendable_value(&burn_addresses), 10);
+    assert_eq!(state.unspendable_value(&HashSet::new()), 0);
+}
+
+#[test]
+fn sm_5_pooled_transfer_feeds_refunds() {
+    let start = State::from([Bill::new(User::Alice, 50, 0)]);
+    let pooled = DigitalCashSystem::try_next_state(
+        &start,
+        &CashTransaction::PooledTransfer {
+            spends: vec![Bill::new(User::Alice, 50, 0)],
+            receives: vec![Bill::new(User::Bob, 30, 1)],
+        },
+    )
+    .unwrap();
+    assert_eq!(pooled.burn_pool(), 20);
+    assert_eq!(pooled.total_supply(), 30);
+
+    let refunded = DigitalCashSystem::try_next_state(
+        &pooled,
+        &CashTransaction::Refund {
+            recipient: User::Alice,
+            amount: 15,
+        },
+    )
+    .unwrap();
+    assert_eq!(refunded.burn_pool(), 5);
+    assert_eq!(
+        refunded.bills,
+        HashSet::from([Bill::new(User::Bob, 30, 1), Bill::new(User::Alice, 15, 2)])
+    );
+
+    assert_eq!(
+        DigitalCashSystem::try_next_state(
+            &refunded,
+            &CashTransaction::Refund {
+                recipient: User::Alice,
+                amount: 6,
+            },
+        ),
+        Err(TransferError::InsufficientPool {
+            available: 5,
+            requested: 6
+        })
+    );
+}
+
+#[test]
+fn sm_5_plain_transfer_does_not_feed_pool() {
+    let start = State::from([Bill::new(User::Alice, 50, 0)]);
+    let end = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 50, 0)],
+            receives: vec![Bill::new(User::Bob, 30, 1)],
+            memo: Vec::new(),
+        },
+    );
+    assert_eq!(end.burn_pool(), 0);
+}
+
+#[test]
+fn sm_5_pay_with_change() {
+    let start = State::from([
+        Bill::new(User::Alice, 10, 0),
+        Bill::new(User::Alice, 25, 1),
+        Bill::new(User::Bob, 5, 2),
+    ]);
+    let (end, transfer) = pay(&start, User::Alice, User::Bob, 20).unwrap();
+
+    let CashTransaction::Transfer {
+        spends, receives, ..
+    } = transfer
+    else {
+        panic!("pay should build a transfer");
+    };
+    assert_eq!(spends, vec![Bill::new(User::Alice, 25, 1)]);
+    assert_eq!(
+        receives,
+        vec![Bill::new(User::Bob, 20, 3), Bill::new(User::Alice, 5, 4)]
+    );
+    assert_eq!(end.balance_of(&User::Alice), 15);
+    assert_eq!(end.balance_of(&User::Bob), 25);
+}
+
+#[test]
+fn sm_5_pay_unaffordable_fails() {
+    let start = State::from([Bill::new(User::Alice, 10, 0), Bill::new(User::Bob, 50, 1)]);
+    assert!(pay(&start, User::Alice, User::Bob, 11).is_none());
+}
+
+#[test]
+fn sm_5_pay_with_overflowing_bills_fails() {
+    let start = State::from([
+        Bill::new(User::Alice, u64::MAX - 1, 0),
+        Bill::new(User::Alice, 5, 1),
+    ]);
+    assert!(pay(&start, User::Alice, User::Bob, u64::MAX).is_none());
+}
+
+#[test]
+fn sm_5_receipt_for_mint() {
+    let start = State::from([Bill::new(User::Alice, 10, 0)]);
+    let (_, receipt) = apply_with_receipt(
+        &start,
+        &CashTransaction::Mint {
+            minter: User::Bob,
+            amount: 20,
+        },
+    );
+    assert_eq!(
+        receipt,
+        TransitionReceipt {
+            created: vec![1],
+            destroyed: vec![],
+            burned: 0,
+            fee: 0,
+            memo: Vec::new(),
+            outcome: Ok(()),
+        }
+    );
+}
+
+#[test]
+fn sm_5_receipt_for_transfer_with_change() {
+    let start = State::from([Bill::new(User::Alice, 10, 0), Bill::new(User::Alice, 25, 1)]);
+    let (end, receipt) = apply_with_receipt(
+        &start,
+        &CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 10, 0), Bill::new(User::Alice, 25, 1)],
+            receives: vec![Bill::new(User::Bob, 30, 2), Bill::new(User::Alice, 3, 3)],
+            memo: Vec::new(),
+        },
+    );
+    assert_eq!(end.total_supply(), 33);
+    assert_eq!(
+        receipt,
+        TransitionReceipt {
+            created: vec![2, 3],
+            destroyed: vec![0, 1],
+            burned: 0,
+            fee: 2,
+            memo: Vec::new(),
+            outcome: Ok(()),
+        }
+    );
+}
+
+#[test]
+fn sm_5_receipt_for_rejected_transfer() {
+    let start = State::from([Bill::new(User::Alice, 10, 0)]);
+    let (end, receipt) = apply_with_receipt(
+        &start,
+        &CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 10, 0)],
+            receives: vec![Bill::new(User::Bob, 11, 1)],
+            memo: Vec::new(),
+        },
+    );
+    assert_eq!(end, start);
+    assert_eq!(
+        receipt,
+        TransitionReceipt {
+            created: vec![],
+            destroyed: vec![],
+            burned: 0,
+            fee: 0,
+            memo: Vec::new(),
+            outcome: Err(TransferError::OutputsExceedInputs {
+                spent: 10,
+                received: 11
+            }),
+        }
+    );
+}
+
+#[test]
+fn sm_5_equalize_plan_evens_out_balances() {
+    let start = State::from([
+        Bill::new(User::Alice, 50, 0),
+        Bill::new(User::Alice, 40, 1),
+        Bill::new(User::Bob, 30, 2),
+    ]);
+    let among = [User::Alice, User::Bob, User::Charlie];
+    let plan = equalize_plan(&start, &among).unwrap();
+
+    let mut end = start;
+    for t in plan.iter() {
+        end = DigitalCashSystem::try_next_state(&end, t).unwrap();
+    }
+    for user in among.iter() {
+        assert_eq!(end.balance_of(user), 40);
+    }
+}
+
+#[test]
+fn sm_5_equalize_plan_among_nobody_fails() {
+    let start = State::from([Bill::new(User::Alice, 50, 0)]);
+    assert!(equalize_plan(&start, &[]).is_none());
+    assert!(equalize_plan(&start, &[User::Alice, User::Alice]).is_none());
+}
+
+#[test]
+fn sm_5_equalize_plan_with_uneven_total_fails() {
+    let start = State::from([Bill::new(User::Alice, 91, 0), Bill::new(User::Bob, 30, 1)]);
+    let among = [User::Alice, User::Bob, User::Charlie];
+    assert!(equalize_plan(&start, &among).is_none());
+}
+
+#[test]
+fn sm_5_any_of_condition_lets_either_user_spend() {
+    let start = State::from([Bill::new(User::Alice, 20, 0)
+        .with_condition(SpendCondition::AnyOf(vec![User::Alice, User::Bob]))]);
+    let signed_by = |signer: User| CashTransaction::SignedTransfer {
+        chain_id: 0,
+        signers: vec![signer],
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![Bill::new(User::Charlie, 20, 1)],
+    };
+
+    assert!(DigitalCashSystem::try_next_state(&start, &signed_by(User::Alice)).is_ok());
+    assert!(DigitalCashSystem::try_next_state(&start, &signed_by(User::Bob)).is_ok());
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&start, &signed_by(User::Charlie)),
+        Err(TransferError::Unauthorized)
+    );
+
+    // an unsigned transfer cannot spend a bill with a condition at all
+    assert_eq!(
+        DigitalCashSystem::try_next_state(
+            &start,
+            &CashTransaction::Transfer {
+                spends: vec![Bill::new(User::Alice, 20, 0)],
+                receives: vec![Bill::new(User::Charlie, 20, 1)],
+                memo: Vec::new(),
+            },
+        ),
+        Err(TransferError::Unauthorized)
+    );
+}
+
+#[test]
+fn sm_5_timelock_blocks_early_spend() {
+    let start =
+        State::from([Bill::new(User::Alice, 20, 0).with_condition(SpendCondition::Timelock(2))]);
+    let transfer = CashTransaction::SignedTransfer {
+        chain_id: 0,
+        signers: vec![User::Alice],
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![Bill::new(User::Bob, 20, 1)],
+    };
+
+    let early = DigitalCashSystem::next_state(&start, &CashTransaction::Tick);
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&early, &transfer),
+        Err(TransferError::Unauthorized)
+    );
+
+    let late = DigitalCashSystem::next_state(&early, &CashTransaction::Tick);
+    let end = DigitalCashSystem::try_next_state(&late, &transfer).unwrap();
+    assert_eq!(end.balance_of(&User::Bob), 20);
+}
+
+#[test]
+fn sm_5_ordered_accessors_are_deterministic() {
+    let bills = [
+        Bill::new(User::Charlie, 7, 0),
+        Bill::new(User::Alice, 20, 1),
+        Bill::new(User::Bob, 7, 2),
+        Bill::new(User::Alice, 3, 3),
+        Bill::new(User::Charlie, 20, 4),
+    ];
+    let forwards = State::from_iter(bills.clone());
+    let mut backwards = State::from_iter(bills.iter().rev().cloned());
+    backwards.set_serial(forwards.next_serial()).unwrap();
+    let mut minted = State::new();
+    for bill in bills.iter() {
+        minted = DigitalCashSystem::next_state(
+            &minted,
+            &CashTransaction::Mint {
+                minter: bill.owner,
+                amount: bill.amount,
+            },
+        );
+    }
+
+    for other in [&backwards, &minted] {
+        assert_eq!(forwards.bills_sorted(), other.bills_sorted());
+        assert_eq!(forwards.bills_by_amount(), other.bills_by_amount());
+        assert_eq!(forwards.holders(), other.holders());
+        assert_eq!(forwards.denominations(), other.denominations());
+        assert_eq!(forwards.to_bytes(), other.to_bytes());
+        assert_eq!(
+            forwards.gini().unwrap().to_bits(),
+            other.gini().unwrap().to_bits()
+        );
+    }
+    assert_eq!(
+        forwards.holders(),
+        vec![User::Alice, User::Bob, User::Charlie]
+    );
+    // minting used up mint allowances, which the root commits to
+    assert_eq!(forwards.state_root(), backwards.state_root());
+    assert_ne!(forwards.state_root(), minted.state_root());
+    assert_eq!(forwards.denominations(), vec![3, 7, 20]);
+}
+
+#[test]
+fn sm_5_escrow_claim_with_correct_secret() {
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
+    let locked = DigitalCashSystem::try_next_state(
+        &start,
+        &CashTransaction::Escrow {
+            bill: Bill::new(User::Alice, 20, 0),
+            claimant: User::Bob,
+            secret_hash: hash32(b"open sesame"),
+        },
+    )
+    .unwrap();
+    assert_eq!(locked.total_supply(), 0);
+    assert_eq!(locked.escrowed_value(), 20);
+
+    let claimed = DigitalCashSystem::try_next_state(
+        &locked,
+        &CashTransaction::Claim {
+            serial: 0,
+            secret: b"open sesame".to_vec(),
+        },
+    )
+    .unwrap();
+    assert_eq!(claimed.escrowed_value(), 0);
+    assert_eq!(claimed.bills, HashSet::from([Bill::new(User::Bob, 20, 1)]));
+    assert_eq!(claimed.provenance(1), vec![0]);
+}
+
+#[test]
+fn sm_5_escrow_claim_with_wrong_secret_fails() {
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
+    let locked = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::Escrow {
+            bill: Bill::new(User::Alice, 20, 0),
+            claimant: User::Bob,
+            secret_hash: hash32(b"open sesame"),
+        },
+    );
+    let claim = CashTransaction::Claim {
+        serial: 0,
+        secret: b"open barley".to_vec(),
+    };
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&locked, &claim),
+        Err(TransferError::WrongSecret)
+    );
+    assert_eq!(DigitalCashSystem::next_state(&locked, &claim), locked);
+}
+
+#[test]
+fn sm_5_escrow_reclaimed_after_timeout() {
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
+    let mut state = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::Escrow {
+            bill: Bill::new(User::Alice, 20, 0),
+            claimant: User::Bob,
+            secret_hash: hash32(b"open sesame"),
+        },
+    );
+    let reclaim = CashTransaction::ReclaimEscrow { serial: 0 };
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&state, &reclaim),
+        Err(TransferError::EscrowNotExpired)
+    );
+
+    for _ in 0..ESCROW_TIMEOUT {
+        state = DigitalCashSystem::next_state(&state, &CashTransaction::Tick);
+    }
+    let end = DigitalCashSystem::try_next_state(&state, &reclaim).unwrap();
+    assert_eq!(end.bills, HashSet::from([Bill::new(User::Alice, 20, 1)]));
+}
+
+#[test]
+fn sm_5_escrow_release_keeps_expiry() {
+    let start = State::from([Bill::new(User::Alice, 20, 0).expiring_at(2)]);
+    let mut state = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::Escrow {
+            bill: Bill::new(User::Alice, 20, 0),
+            claimant: User::Bob,
+            secret_hash: hash32(b"open sesame"),
+        },
+    );
+    for _ in 0..ESCROW_TIMEOUT {
+        state = DigitalCashSystem::next_state(&state, &CashTransaction::Tick);
+    }
+    let reclaimed =
+        DigitalCashSystem::try_next_state(&state, &CashTransaction::ReclaimEscrow { serial: 0 })
+            .unwrap();
+    let released = reclaimed.bills.get(&Bill::new(User::Alice, 20, 1)).unwrap();
+    assert_eq!(released.expires_at, 2);
+
+    // the released bill cannot be spent, and the next tick takes it out of circulation
+    assert_eq!(
+        DigitalCashSystem::try_next_state(
+            &reclaimed,
+            &CashTransaction::Transfer {
+                spends: vec![Bill::new(User::Alice, 20, 1)],
+                receives: vec![Bill::new(User::Bob, 20, 2)],
+                memo: Vec::new(),
+            },
+        ),
+        Err(TransferError::ExpiredBill { serial: 1 })
+    );
+    let end = DigitalCashSystem::next_state(&reclaimed, &CashTransaction::Tick);
+    assert!(end.bills.is_empty());
+    assert_eq!(end.total_burned(), 20);
+}
+
+/// Check that the input is a single well-formed JSON value
+#[cfg(test)]
+fn is_valid_json(input: &str) -> bool {
+    fn skip_ws(s: &[u8], i: &mut usize) {
+        while *i < s.len() && s[*i].is_ascii_whitespace() {
+            *i += 1;
+        }
+    }
+
+    fn string(s: &[u8], i: &mut usize) -> bool {
+        *i += 1;
+        while *i < s.len() {
+            match s[*i] {
+                b'"' => {
+                    *i += 1;
+                    return true;
+                }
+                b'\\' => *i += 2,
+                _ => *i += 1,
+            }
+        }
+        false
+    }
+
+    fn value(s: &[u8], i: &mut usize) -> bool {
+        skip_ws(s, i);
+        let Some(&c) = s.get(*i) else {
+            return false;
+        };
+        match c {
+            b'{' | b'[' => {
+                let close = if c == b'{' { b'}' } else { b']' };
+                *i += 1;
+                skip_ws(s, i);
+                if s.get(*i) == Some(&close) {
+                    *i += 1;
+                    return true;
+                }
+                loop {
+                    if c == b'{' {
+                        skip_ws(s, i);
+                        if s.get(*i) != Some(&b'"') || !string(s, i) {
+                            return false;
+                        }
+                        skip_ws(s, i);
+                        if s.get(*i) != Some(&b':') {
+                            return false;
+                        }
+                        *i += 1;
+                    }
+                    if !value(s, i) {
+                        return false;
+                    }
+                    skip_ws(s, i);
+                    match s.get(*i) {
+                        Some(b',') => *i += 1,
+                        Some(&b) if b == close => {
+                            *i += 1;
+                            return true;
+                        }
+                        _ => return false,
+                    }
+                }
+            }
+            b'"' => string(s, i),
+            b'-' | b'0'..=b'9' => {
+                let start = *i;
+                while *i < s.len()
+                    && matches!(s[*i], b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')
+                {
+                    *i += 1;
+                }
+                std::str::from_utf8(&s[start..*i])
+                    .map(|n| n.parse::<f64>().is_ok())
+                    .unwrap_or(false)
+            }
+            _ => ["true", "false", "null"].iter().any(|literal| {
+                let matched = s[*i..].starts_with(literal.as_bytes());
+                if matched {
+                    *i += literal.len();
+                }
+                matched
+            }),
+        }
+    }
+
+    let s = input.as_bytes();
+    let mut i = 0;
+    let ok = value(s, &mut i);
+    skip_ws(s, &mut i);
+    ok && i == s.len()
+}
+
+#[test]
+fn sm_5_json_schema_is_valid_json_covering_every_variant() {
+    let schema = CashTransaction::json_schema();
+    assert!(is_valid_json(&schema));
+    assert!(!is_valid_json("{\"oneOf\":[}"));
+
+    for variant in [
+        "Mint",
+        "MintAndSend",
+        "Airdrop",
+        "Transfer",
+        "SignedTransfer",
+        "PooledTransfer",
+        "Refund",
+        "Escrow",
+        "Claim",
+        "ReclaimEscrow",
+        "ShareBills",
+        "ExitShare",
+        "Burn",
+        "PartialBurn",
+        "Tick",
+        "Nop",
+        "Approve",
+        "SetPaused",
+        "CapBalance",
+        "ApplyDemurrage",
+        "RotateKey",
+        "ConditionalTransfer",
+    ] {
+        assert!(
+            schema.contains(&format!("\"{variant}\"")),
+            "missing {variant}"
+        );
+    }
+}
+
+#[test]
+fn sm_5_find_double_spends_reports_conflicting_pair() {
+    let txs = [
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Bob, 20, 3)],
+            memo: Vec::new(),
+        },
+        CashTransaction::Burn {
+            signer: User::Bob,
+            spends: vec![Bill::new(User::Bob, 10, 1)],
+        },
+        CashTransaction::Transfer {
+            spends: vec![
+                Bill::new(User::Charlie, 5, 2),
+                Bill::new(User::Alice, 20, 0),
+            ],
+            receives: vec![Bill::new(User::Charlie, 25, 3)],
+            memo: Vec::new(),
+        },
+    ];
+    assert_eq!(find_double_spends(&txs), vec![(0, 2, 0)]);
+    assert_eq!(find_double_spends(&txs[..2]), vec![]);
+}
+
+#[test]
+fn sm_5_find_double_spends_allows_co_owners_exiting_together() {
+    let exit = |shareholder| CashTransaction::ExitShare {
+        serial: 2,
+        shareholder,
+    };
+    let txs = [exit(User::Alice), exit(User::Bob)];
+    let start = State::from([Bill::new(User::Alice, 30, 0), Bill::new(User::Bob, 20, 1)]);
+    let shared = DigitalCashSystem::try_next_state(
+        &start,
+        &CashTransaction::ShareBills {
+            spends: vec![Bill::new(User::Alice, 30, 0), Bill::new(User::Bob, 20, 1)],
+            shares: vec![(User::Alice, 30), (User::Bob, 20)],
+        },
+    )
+    .unwrap();
+    let (_, outcomes) = apply_each(&shared, &txs);
+    assert!(outcomes.iter().all(Result::is_ok));
+    assert!(find_double_spends(&txs).is_empty());
+
+    // the same co-owner cannot exit twice
+    let txs = [exit(User::Alice), exit(User::Bob), exit(User::Alice)];
+    assert_eq!(find_double_spends(&txs), vec![(0, 2, 2)]);
+}
+
+#[test]
+fn sm_5_mints_are_rate_limited_per_epoch() {
+    let mint = CashTransaction::Mint {
+        minter: User::Alice,
+        amount: 10,
+    };
+    let mut state = State::new();
+    for _ in 0..MAX_MINTS_PER_EPOCH {
+        state = DigitalCashSystem::try_next_state(&state, &mint).unwrap();
+    }
+    assert_eq!(state.balance_of(&User::Alice), 10 * MAX_MINTS_PER_EPOCH);
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&state, &mint),
+        Err(TransferError::MintRateExceeded)
+    );
+
+    // the limit is per minter
+    let bob_mint = CashTransaction::Mint {
+        minter: User::Bob,
+        amount: 10,
+    };
+    assert!(DigitalCashSystem::try_next_state(&state, &bob_mint).is_ok());
+
+    let state = DigitalCashSystem::next_state(&state, &CashTransaction::Tick);
+    let state = DigitalCashSystem::try_next_state(&state, &mint).unwrap();
+    assert_eq!(
+        state.balance_of(&User::Alice),
+        10 * (MAX_MINTS_PER_EPOCH + 1)
+    );
+}
+
+#[test]
+fn sm_5_to_dot_draws_bills_and_parent_edges() {
+    let start = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 5, 1)]);
+    let end = DigitalCashSystem::try_next_state(
+        &start,
+        &CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Bob, 12, 2), Bill::new(User::Charlie, 8, 3)],
+            memo: Vec::new(),
+        },
+    )
+    .unwrap();
+
+    let dot = end.to_dot();
+    assert!(dot.starts_with("digraph provenance {"));
+    assert!(dot.contains("bill1 [label=\"Bob 5 #1\"];"));
+    assert!(dot.contains("bill2 [label=\"Bob 12 #2\"];"));
+    assert!(dot.contains("bill3 [label=\"Charlie 8 #3\"];"));
+    assert!(dot.contains("bill0 [label=\"#0\", style=dashed];"));
+    assert!(dot.contains("bill0 -> bill2;"));
+    assert!(dot.contains("bill0 -> bill3;"));
+    assert_eq!(dot.matches("->").count(), 2);
+}
+
+#[test]
+fn sm_5_transfers_needed_within_input_cap() {
+    let state = State::from([
+        Bill::new(User::Alice, 50, 0),
+        Bill::new(User::Alice, 30, 1),
+        Bill::new(User::Bob, 10, 2),
+    ]);
+    assert_eq!(
+        transfers_needed(&state, &User::Alice, 60, &User::Bob),
+        Some(1)
+    );
+    assert_eq!(
+        transfers_needed(&state, &User::Alice, 0, &User::Bob),
+        Some(0)
+    );
+    assert_eq!(transfers_needed(&state, &User::Alice, 81, &User::Bob), None);
+}
+
+#[test]
+fn sm_5_transfers_needed_split_by_input_cap() {
+    let bills: State = (0..MAX_INPUTS as u64 * 2 + 1)
+        .map(|serial| Bill::new(User::Alice, 10, serial))
+        .collect();
+    let all = 10 * (MAX_INPUTS as u64 * 2 + 1);
+    assert_eq!(
+        transfers_needed(&bills, &User::Alice, all, &User::Bob),
+        Some(3)
+    );
+    assert_eq!(
+        transfers_needed(&bills, &User::Alice, 10 * MAX_INPUTS as u64 + 1, &User::Bob),
+        Some(2)
+    );
+}
+
+#[test]
+fn sm_5_transfer_memo_reaches_receipt() {
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
+    let transfer = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![Bill::new(User::Bob, 20, 1)],
+        memo: b"invoice 42".to_vec(),
+    };
+    let (end, receipt) = apply_with_receipt(&start, &transfer);
+    assert_eq!(receipt.outcome, Ok(()));
+    assert_eq!(receipt.memo, b"invoice 42".to_vec());
+    assert_eq!(end.balance_of(&User::Bob), 20);
+}
+
+#[test]
+fn sm_5_transfer_memo_too_long_fails() {
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
+    let transfer = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![Bill::new(User::Bob, 20, 1)],
+        memo: vec![0; MAX_MEMO_LEN + 1],
+    };
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&start, &transfer),
+        Err(TransferError::MemoTooLong)
+    );
+    let (end, receipt) = apply_with_receipt(&start, &transfer);
+    assert_eq!(end, start);
+    assert_eq!(receipt.outcome, Err(TransferError::MemoTooLong));
+}
+
+/// Try to spend the given bill twice, first paying Bob and then paying Charlie with it. Each
+/// transfer is otherwise valid, so the only thing that can stop the second payment is that the
+/// bill has already been spent. Returns whether the second payment was rejected for exactly
+/// that reason.
+#[cfg(test)]
+fn attempt_double_spend(state: &State, bill: &Bill) -> bool {
+    let pay = |recipient: User, serial: u64| CashTransaction::Transfer {
+        spends: vec![bill.clone()],
+        receives: vec![Bill::new(recipient, bill.amount, serial)],
+        memo: Vec::new(),
+    };
+    let first = pay(User::Bob, state.next_serial);
+    let Ok(after_first) = DigitalCashSystem::try_next_state(state, &first) else {
+        return false;
+    };
+    let second = pay(User::Charlie, after_first.next_serial);
+    DigitalCashSystem::try_next_state(&after_first, &second)
+        == Err(TransferError::NonexistentBill {
+            serial: bill.serial,
+        })
+}
+
+/// A deliberately naive model of the digital cash system, used as a reference to test the
+/// real implementation against. It only understands the transactions that
+/// [`arbitrary_tx`] generates, and refuses to judge any other kind.
+#[cfg(test)]
+#[derive(Default)]
+struct ReferenceCash {
+    bills: HashMap<u64, (User, u64)>,
+    next_serial: u64,
+    mints: HashMap<User, u64>,
+}
+
+#[cfg(test)]
+impl ReferenceCash {
+    /// Apply the transaction, or leave the model untouched and return false if it is invalid.
+    /// Returns `None` for the kinds of transaction the model does not know.
+    fn apply(&mut self, t: &CashTransaction) -> Option<bool> {
+        match t {
+            CashTransaction::Mint { minter, amount } => {
+                let mints = self.mints.entry(*minter).or_default();
+                if *mints == MAX_MINTS_PER_EPOCH || *amount == 0 || *amount > MAX_BILL_AMOUNT {
+                    return Some(false);
+                }
+                *mints += 1;
+                self.bills.insert(self.next_serial, (*minter, *amount));
+                self.next_serial += 1;
+                Some(true)
+            }
+            CashTransaction::Tick => {
+                self.mints.clear();
+                Some(true)
+            }
+            CashTransaction::Transfer {
+                spends, receives, ..
+            } => {
+                let Some(spent) = self.spend(spends) else {
+                    return Some(false);
+                };
+                let mut received: u128 = 0;
+                for (i, bill) in receives.iter().enumerate() {
+                    if bill.amount == 0
+                        || bill.amount > MAX_BILL_AMOUNT
+                        || bill.serial != self.next_serial + i as u64
+                    {
+                        return Some(false);
+                    }
+                    received += bill.amount as u128;
+                }
+                if receives.is_empty() || received > spent {
+                    return Some(false);
+                }
+                for bill in spends {
+                    self.bills.remove(&bill.serial);
+                }
+                for bill in receives {
+                    self.bills.insert(bill.serial, (bill.owner, bill.amount));
+                }
+                self.next_serial += receives.len() as u64;
+                Some(true)
+            }
+            CashTransaction::Burn { spends, .. } => {
+                if self.spend(spends).is_none() {
+                    return Some(false);
+                }
+                for bill in spends {
+                    self.bills.remove(&bill.serial);
+                }
+                Some(true)
+            }
+            CashTransaction::MintAndSend { .. }
+            | CashTransaction::Airdrop { .. }
+            | CashTransaction::SignedTransfer { .. }
+            | CashTransaction::PooledTransfer { .. }
+            | CashTransaction::Refund { .. }
+            | CashTransaction::Escrow { .. }
+            | CashTransaction::Claim { .. }
+            | CashTransaction::ReclaimEscrow { .. }
+            | CashTransaction::ShareBills { .. }
+            | CashTransaction::ExitShare { .. }
+            | CashTransaction::PartialBurn { .. }
+            | CashTransaction::Nop
+            | CashTransaction::Approve { .. }
+            | CashTransaction::SetPaused { .. }
+            | CashTransaction::CapBalance { .. }
+            | CashTransaction::ApplyDemurrage { .. }
+            | CashTransaction::RotateKey { .. }
+            | CashTransaction::ConditionalTransfer { .. } => None,
+        }
+    }
+
+    /// The total value of the given bills, if they are a nonempty set of distinct bills that
+    /// are all in circulation
+    fn spend(&self, spends: &[Bill]) -> Option<u128> {
+        let mut total: u128 = 0;
+        for (i, bill) in spends.iter().enumerate() {
+            if spends[..i].iter().any(|other| other.serial == bill.serial)
+                || self.bills.get(&bill.serial) != Some(&(bill.owner, bill.amount))
+            {
+                return None;
+            }
+            total += bill.amount as u128;
+        }
+        (!spends.is_empty()).then_some(total)
+    }
+
+    /// Whether the real state holds exactly the same bills and next serial as this model
+    fn matches(&self, state: &State) -> bool {
+        let bills: HashMap<u64, (User, u64)> = state
+            .bills
+            .iter()
+            .map(|bill| (bill.serial, (bill.owner, bill.amount)))
+            .collect();
+        bills == self.bills && state.next_serial == self.next_serial
+    }
+}
+
+#[test]
+fn sm_5_differential_against_reference_model() {
+    let mut rng = XorShiftRng::seed_from_u64(7);
+    let mut state = State::new();
+    let mut reference = ReferenceCash::default();
+
+    for step in 0..5000 {
+        let t = arbitrary_tx(&state, &mut rng, 0.25);
+        let Some(applied) = reference.apply(&t) else {
+            panic!(
+                "step {step} generated a {}, which the model does not know",
+                t.name()
+            );
+        };
+        let result = DigitalCashSystem::try_next_state(&state, &t);
+        assert_eq!(result.is_ok(), applied, "step {step} disagrees on validity");
+
+        state = DigitalCashSystem::next_state(&state, &t);
+        assert!(reference.matches(&state), "step {step} diverged");
+    }
+}
+
+#[test]
+fn sm_5_quantize_to_uniform_units() {
+    let start = State::from([
+        Bill::new(User::Alice, 25, 0),
+        Bill::new(User::Alice, 30, 1),
+        Bill::new(User::Bob, 7, 2),
+        Bill::new(User::Charlie, 10, 3),
+    ]);
+    let (end, transactions) = quantize(&start, 10).unwrap();
+
+    // Charlie already holds a single unit, so only Alice and Bob need transactions
+    assert_eq!(transactions.len(), 2);
+    assert!(end.bills.iter().all(|bill| bill.amount == 10));
+    assert_eq!(end.balance_of(&User::Alice), 50);
+    assert_eq!(end.balance_of(&User::Bob), 0);
+    assert_eq!(end.balance_of(&User::Charlie), 10);
+    assert_eq!(end.total_supply(), start.total_supply() - 5 - 7);
+    assert_eq!(end.total_burned(), 7);
+}
+
+#[test]
+fn sm_5_quantize_splits_fanout_over_several_transfers() {
+    let start = State::from([Bill::new(User::Alice, 105, 0)]);
+    let (end, transactions) = quantize(&start, 10).unwrap();
+
+    // one bill may only fan out into FANOUT_FACTOR bills, so change is passed along
+    assert!(transactions.len() > 1);
+    for t in transactions.iter() {
+        let CashTransaction::Transfer {
+            spends, receives, ..
+        } = t
+        else {
+            panic!("expected a transfer");
+        };
+        assert!(receives.len() <= spends.len() * FANOUT_FACTOR);
+    }
+    assert_eq!(end.bills.len(), 10);
+    assert!(end.bills.iter().all(|bill| bill.amount == 10));
+    assert_eq!(end.balance_of(&User::Alice), 100);
+    assert_eq!(end.total_supply(), 100);
+}
+
+#[test]
+fn sm_5_quantize_reports_failures() {
+    // the change bill passed along leaves nothing over to pay the tax
+    let mut start = State::from([Bill::new(User::Alice, 105, 0)]);
+    start.set_transfer_tax(true);
+    assert_eq!(
+        quantize(&start, 10).map(|(_, transactions)| transactions.len()),
+        Err(TransferError::InsufficientTax {
+            required: 2,
+            paid: 0
+        })
+    );
+}
+
+#[test]
+fn sm_5_highest_serial_of_fresh_state_is_none() {
+    assert_eq!(State::new().highest_serial(), None);
+}
+
+#[test]
+fn sm_5_highest_serial_after_minting() {
+    let mint = CashTransaction::Mint {
+        minter: User::Alice,
+        amount: 20,
+    };
+    let state = DigitalCashSystem::next_state(&State::new(), &mint);
+    assert_eq!(state.highest_serial(), Some(0));
+    let state = DigitalCashSystem::next_state(&state, &mint);
+    assert_eq!(state.highest_serial(), Some(1));
+}
+
+#[test]
+fn sm_5_share_above_bill_cap_fails() {
+    let start = State::from([
+        Bill::new(User::Alice, MAX_BILL_AMOUNT, 0),
+        Bill::new(User::Alice, MAX_BILL_AMOUNT, 1),
+    ]);
+    let spends = vec![
+        Bill::new(User::Alice, MAX_BILL_AMOUNT, 0),
+        Bill::new(User::Alice, MAX_BILL_AMOUNT, 1),
+    ];
+    assert_eq!(
+        DigitalCashSystem::try_next_state(
+            &start,
+            &CashTransaction::ShareBills {
+                spends: spends.clone(),
+                shares: vec![(User::Alice, 2 * MAX_BILL_AMOUNT)],
+            },
+        ),
+        Err(TransferError::AmountTooLarge)
+    );
+    let shared = DigitalCashSystem::try_next_state(
+        &start,
+        &CashTransaction::ShareBills {
+            spends,
+            shares: vec![(User::Alice, MAX_BILL_AMOUNT), (User::Bob, MAX_BILL_AMOUNT)],
+        },
+    )
+    .unwrap();
+    let exited = DigitalCashSystem::try_next_state(
+        &shared,
+        &CashTransaction::ExitShare {
+            serial: 2,
+            shareholder: User::Bob,
+        },
+    )
+    .unwrap();
+    assert!(exited
+        .bills
+        .contains(&Bill::new(User::Bob, MAX_BILL_AMOUNT, 3)));
+
+    // a share that is somehow too large still cannot be exited into a single bill
+    let mut oversized = State::new();
+    oversized.shared_bills.insert(
+        0,
+        SharedBill {
+            shares: vec![(User::Alice, 2 * MAX_BILL_AMOUNT)],
+            serial: 0,
+        },
+    );
+    oversized.increment_serial().unwrap();
+    assert_eq!(
+        DigitalCashSystem::try_next_state(
+            &oversized,
+            &CashTransaction::ExitShare {
+                serial: 0,
+                shareholder: User::Alice,
+            },
+        ),
+        Err(TransferError::AmountTooLarge)
+    );
+}
+
+#[test]
+fn sm_5_co_owner_exits_shared_bill_with_their_share() {
+    let start = State::from([Bill::new(User::Alice, 30, 0), Bill::new(User::Bob, 20, 1)]);
+    let shared = DigitalCashSystem::try_next_state(
+        &start,
+        &CashTransaction::ShareBills {
+            spends: vec![Bill::new(User::Alice, 30, 0), Bill::new(User::Bob, 20, 1)],
+            shares: vec![(User::Alice, 30), (User::Bob, 20)],
+        },
+    )
+    .unwrap();
+    assert!(shared.bills.is_empty());
+    assert_eq!(shared.shared_bill(2).map(SharedBill::amount), Some(50));
+    assert_eq!(shared.balance_of(&User::Alice), 30);
+    assert_eq!(shared.balance_of(&User::Bob), 20);
+    assert_eq!(shared.total_supply(), 50);
+
+    let exited = DigitalCashSystem::try_next_state(
+        &shared,
+        &CashTransaction::ExitShare {
+            serial: 2,
+            shareholder: User::Bob,
+        },
+    )
+    .unwrap();
+    assert_eq!(exited.bills, HashSet::from([Bill::new(User::Bob, 20, 3)]));
+    assert_eq!(exited.provenance(3), vec![2]);
+    assert_eq!(exited.shared_bill(2).map(SharedBill::amount), Some(30));
+    assert_eq!(exited.balance_of(&User::Alice), 30);
+    assert_eq!(exited.balance_of(&User::Bob), 20);
+    assert_eq!(exited.total_supply(), 50);
+
+    // Bob has nothing left to take out
+    assert_eq!(
+        DigitalCashSystem::try_next_state(
+            &exited,
+            &CashTransaction::ExitShare {
+                serial: 2,
+                shareholder: User::Bob,
+            },
+        ),
+        Err(TransferError::NotAShareholder)
+    );
+
+    // the last co-owner to exit takes the bill out of circulation
+    let end = DigitalCashSystem::try_next_state(
+        &exited,
+        &CashTransaction::ExitShare {
+            serial: 2,
+            shareholder: User::Alice,
+        },
+    )
+    .unwrap();
+    assert_eq!(end.shared_bill(2), None);
+    assert_eq!(end.balance_of(&User::Alice), 30);
+}
+
+#[test]
+fn sm_5_shares_exceeding_spent_value_fail() {
+    let start = State::from([Bill::new(User::Alice, 30, 0)]);
+    let share = CashTransaction::ShareBills {
+        spends: vec![Bill::new(User::Alice, 30, 0)],
+        shares: vec![(User::Alice, 20), (User::Charlie, 11)],
+    };
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&start, &share),
+        Err(TransferError::OutputsExceedInputs {
+            spent: 30,
+            received: 31
+        })
+    );
+}
+
+#[test]
+fn sm_5_mint_zero_fails() {
+    let start = State::new();
+    for mint in [
+        CashTransaction::Mint {
+            minter: User::Alice,
+            amount: 0,
+        },
+        CashTransaction::MintAndSend {
+            minter: User::Alice,
+            amount: 0,
+            recipient: User::Bob,
+        },
+    ] {
+        assert_eq!(
+            DigitalCashSystem::try_next_state(&start, &mint),
+            Err(TransferError::ZeroAmountOutput)
+        );
+        assert!(!DigitalCashSystem::next_state(&start, &mint).has_zero_value_bills());
+    }
+}
+
+#[test]
+fn sm_5_issuance_past_end_of_serial_space_fails() {
+    let start = State::new_with_serial_base(u64::MAX);
+    let mint = CashTransaction::Mint {
+        minter: User::Alice,
+        amount: 20,
+    };
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&start, &mint),
+        Err(TransferError::SerialSpaceExhausted)
+    );
+    assert_eq!(DigitalCashSystem::next_state(&start, &mint), start);
+    assert_eq!(
+        DigitalCashSystem::try_next_state(
+            &start,
+            &CashTransaction::MintAndSend {
+                minter: User::Alice,
+                amount: 20,
+                recipient: User::Bob,
+            },
+        ),
+        Err(TransferError::SerialSpaceExhausted)
+    );
+
+    // the last serial can still be handed out
+    let start = State::new_with_serial_base(u64::MAX - 1);
+    let end = DigitalCashSystem::try_next_state(&start, &mint).unwrap();
+    assert_eq!(
+        end.bills_sorted(),
+        vec![&Bill::new(User::Alice, 20, u64::MAX - 1)]
+    );
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&end, &mint),
+        Err(TransferError::SerialSpaceExhausted)
+    );
+}
+
+#[test]
+fn sm_5_transfer_past_end_of_serial_space_fails() {
+    let mut start = State::from([Bill::new(User::Alice, 20, 0)]);
+    start.set_serial(u64::MAX - 1).unwrap();
+
+    let transfer = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![
+            Bill::new(User::Bob, 10, u64::MAX - 1),
+            Bill::new(User::Charlie, 10, u64::MAX),
+        ],
+        memo: Vec::new(),
+    };
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&start, &transfer),
+        Err(TransferError::SerialSpaceExhausted)
+    );
+    assert_eq!(DigitalCashSystem::next_state(&start, &transfer), start);
+
+    // a single output still fits
+    let transfer = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![Bill::new(User::Bob, 20, u64::MAX - 1)],
+        memo: Vec::new(),
+    };
+    let end = DigitalCashSystem::try_next_state(&start, &transfer).unwrap();
+    assert_eq!(end.next_serial(), u64::MAX);
+}
+
+#[test]
+fn sm_5_restore_serial_forward() {
+    let mut state = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 5, 1)]);
+    let checkpoint = state.serial_checkpoint();
+    assert_eq!(checkpoint, 2);
+
+    state.set_serial(10).unwrap();
+    assert_eq!(state.restore_serial(checkpoint), Ok(()));
+    assert_eq!(state.next_serial(), 2);
+    assert_eq!(state.restore_serial(7), Ok(()));
+    assert_eq!(state.next_serial(), 7);
+}
+
+#[test]
+fn sm_5_restore_serial_onto_circulating_bill_fails() {
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
+    let checkpoint = start.serial_checkpoint();
+    let mut state = DigitalCashSystem::try_next_state(
+        &start,
+        &CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Bob, 20, 1)],
+            memo: Vec::new(),
+        },
+    )
+    .unwrap();
+
+    // rewinding to before the transfer would hand out serial 1 a second time
+    assert_eq!(
+        state.restore_serial(checkpoint),
+        Err(SerialError::WouldReuseSerial { serial: 1 })
+    );
+    assert_eq!(state.next_serial(), 2);
+}
+
+#[test]
+fn sm_5_max_single_transfer_takes_largest_bills() {
+    let state = State::from([
+        Bill::new(User::Alice, 5, 0),
+        Bill::new(User::Alice, 40, 1),
+        Bill::new(User::Bob, 100, 2),
+        Bill::new(User::Alice, 10, 3),
+        Bill::new(User::Alice, 30, 4),
+        Bill::new(User::Alice, 20, 5),
+        Bill::new(User::Alice, 1, 6),
+    ]);
+    assert_eq!(
+        state.max_single_transfer(&User::Alice, MAX_INPUTS),
+        40 + 30 + 20 + 10
+    );
+    assert_eq!(state.max_single_transfer(&User::Alice, 2), 40 + 30);
+    assert_eq!(
+        state.max_single_transfer(&User::Alice, 100),
+        state.balance_of(&User::Alice)
+    );
+    assert_eq!(state.max_single_transfer(&User::Charlie, MAX_INPUTS), 0);
+}
+
+#[test]
+fn sm_5_run_matches_manual_application() {
+    let transitions = vec![
+        CashTransaction::Mint {
+            minter: User::Alice,
+            amount: 20,
+        },
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Bob, 15, 1), Bill::new(User::Alice, 5, 2)],
+            memo: Vec::new(),
+        },
+        // rejected: already spent
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Charlie, 20, 3)],
+            memo: Vec::new(),
+        },
+        CashTransaction::Tick,
+    ];
+
+    let mut manual = State::new();
+    for t in transitions.iter() {
+        manual = DigitalCashSystem::next_state(&manual, t);
+    }
+    assert_eq!(
+        super::run::<DigitalCashSystem>(State::new(), transitions),
+        manual
+    );
+}
+
+#[test]
+fn sm_5_same_owner_round_trip_is_economic_noop() {
+    let state = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 5, 1)]);
+    let round_trip = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![Bill::new(User::Alice, 12, 2), Bill::new(User::Alice, 8, 3)],
+        memo: Vec::new(),
+    };
+    assert!(is_economic_noop(&state, &round_trip));
+}
+
+#[test]
+fn sm_5_payment_is_not_economic_noop() {
+    let state = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 5, 1)]);
+    let payment = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![Bill::new(User::Bob, 12, 2), Bill::new(User::Alice, 8, 3)],
+        memo: Vec::new(),
+    };
+    assert!(!is_economic_noop(&state, &payment));
+
+    // burning change is not a no-op either
+    let lossy = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![Bill::new(User::Alice, 19, 2)],
+        memo: Vec::new(),
+    };
+    assert!(!is_economic_noop(&state, &lossy));
+}
+
+#[test]
+fn sm_5_burn_amount_exact() {
+    let state = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Alice, 5, 1)]);
+    let burn = burn_amount(&state, &User::Alice, 25).unwrap();
+    assert!(matches!(burn, CashTransaction::Burn { .. }));
+    let end = DigitalCashSystem::try_next_state(&state, &burn).unwrap();
+    assert_eq!(end.balance_of(&User::Alice), 0);
+    assert_eq!(end.total_burned(), 25);
+}
+
+#[test]
+fn sm_5_burn_amount_with_change() {
+    let state = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 5, 1)]);
+    let burn = burn_amount(&state, &User::Alice, 7).unwrap();
+    let (end, events) = DigitalCashSystem::apply_with_events(&state, &burn).unwrap();
+    assert_eq!(
+        end.bills_sorted(),
+        vec![&Bill::new(User::Bob, 5, 1), &Bill::new(User::Alice, 13, 2)]
+    );
+    assert_eq!(end.total_supply(), state.total_supply() - 7);
+    assert_eq!(end.total_burned(), 7);
+    assert_eq!(
+        events,
+        vec![CashEvent::ValueBurned {
+            signer: User::Alice,
+            amount: 7,
+        }]
+    );
+}
+
+#[test]
+fn sm_5_burn_amount_with_overflowing_bills_fails() {
+    let state = State::from([
+        Bill::new(User::Alice, u64::MAX - 1, 0),
+        Bill::new(User::Alice, 5, 1),
+    ]);
+    assert!(burn_amount(&state, &User::Alice, u64::MAX).is_none());
+}
+
+#[test]
+fn sm_5_burn_amount_beyond_balance_fails() {
+    let state = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 5, 1)]);
+    assert!(burn_amount(&state, &User::Alice, 21).is_none());
+    assert!(burn_amount(&state, &User::Charlie, 1).is_none());
+}
+
+#[test]
+fn sm_5_serial_consistency_of_valid_state() {
+    let state = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 5, 1)]);
+    assert_eq!(state.check_serial_consistency(), Ok(()));
+    assert_eq!(State::new().check_serial_consistency(), Ok(()));
+}
+
+#[test]
+fn sm_5_serial_consistency_after_bad_set_serial() {
+    // set_serial refuses to do this, so corrupt the counter directly
+    let mut state = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 5, 1)]);
+    state.next_serial = 0;
+    assert_eq!(state.check_serial_consistency(), Err(0));
+    state.next_serial = 1;
+    assert_eq!(state.check_serial_consistency(), Err(1));
+}
+
+#[test]
+fn sm_5_set_serial_onto_circulating_bill_fails() {
+    let mut state = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 5, 1)]);
+    assert_eq!(
+        state.set_serial(1),
+        Err(SerialError::WouldReuseSerial { serial: 1 })
+    );
+    assert_eq!(
+        state.set_serial(0),
+        Err(SerialError::WouldReuseSerial { serial: 1 })
+    );
+    assert_eq!(state.next_serial(), 2);
+    assert_eq!(state.set_serial(2), Ok(()));
+    assert_eq!(state.set_serial(9), Ok(()));
+    assert_eq!(state.next_serial(), 9);
+}
+
+#[test]
+fn sm_5_outputs_to_sender_are_their_change() {
+    let transfer = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 10, 1)],
+        receives: vec![
+            Bill::new(User::Charlie, 12, 2),
+            Bill::new(User::Alice, 8, 3),
+            Bill::new(User::Bob, 4, 4),
+            Bill::new(User::Alice, 5, 5),
+        ],
+        memo: Vec::new(),
+    };
+    assert_eq!(
+        outputs_to(&transfer, &User::Alice),
+        vec![&Bill::new(User::Alice, 8, 3), &Bill::new(User::Alice, 5, 5)]
+    );
+    assert_eq!(
+        outputs_to(&transfer, &User::Charlie),
+        vec![&Bill::new(User::Charlie, 12, 2)]
+    );
+    assert!(outputs_to(&CashTransaction::Tick, &User::Alice).is_empty());
+}
+
+#[test]
+fn sm_5_serial_bloom_has_no_false_negatives() {
+    let state: State = (0..50)
+        .map(|serial| Bill::new(User::Alice, 10, serial))
+        .collect();
+    let bloom = state.serial_bloom();
+    assert!((0..50).all(|serial| bloom.might_contain(serial)));
+
+    // with only 50 serials spread over 1024 bits, false positives should be rare
+    let false_positives = (1_000..2_000)
+        .filter(|serial| bloom.might_contain(*serial))
+        .count();
+    assert!(false_positives < 20);
+    assert!(!State::new().serial_bloom().might_contain(0));
+}
+
+#[test]
+fn sm_5_mint_above_bill_cap_fails() {
+    let mint = CashTransaction::Mint {
+        minter: User::Alice,
+        amount: MAX_BILL_AMOUNT + 1,
+    };
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&State::new(), &mint),
+        Err(TransferError::AmountTooLarge)
+    );
+
+    let mint = CashTransaction::Mint {
+        minter: User::Alice,
+        amount: MAX_BILL_AMOUNT,
+    };
+    assert!(DigitalCashSystem::try_next_state(&State::new(), &mint).is_ok());
+}
+
+#[test]
+fn sm_5_large_transfer_must_split_below_bill_cap() {
+    let start = State::from([
+        Bill::new(User::Alice, MAX_BILL_AMOUNT, 0),
+        Bill::new(User::Alice, MAX_BILL_AMOUNT, 1),
+    ]);
+    let merged = CashTransaction::Transfer {
+        spends: vec![
+            Bill::new(User::Alice, MAX_BILL_AMOUNT, 0),
+            Bill::new(User::Alice, MAX_BILL_AMOUNT, 1),
+        ],
+        receives: vec![Bill::new(User::Bob, 2 * MAX_BILL_AMOUNT, 2)],
+        memo: Vec::new(),
+    };
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&start, &merged),
+        Err(TransferError::AmountTooLarge)
+    );
+
+    let split = CashTransaction::Transfer {
+        spends: vec![
+            Bill::new(User::Alice, MAX_BILL_AMOUNT, 0),
+            Bill::new(User::Alice, MAX_BILL_AMOUNT, 1),
+        ],
+        receives: vec![
+            Bill::new(User::Bob, MAX_BILL_AMOUNT, 2),
+            Bill::new(User::Bob, MAX_BILL_AMOUNT / 2, 3),
+            Bill::new(User::Bob, MAX_BILL_AMOUNT / 2, 4),
+        ],
+        memo: Vec::new(),
+    };
+    let end = DigitalCashSystem::try_next_state(&start, &split).unwrap();
+    assert_eq!(end.balance_of(&User::Bob), 2 * MAX_BILL_AMOUNT);
+}
+
+#[test]
+fn sm_5_double_spend_attacks_are_defeated() {
+    let mut rng = XorShiftRng::seed_from_u64(3);
+    let mut state = State::new();
+    for _ in 0..200 {
+        state = DigitalCashSystem::next_state(&state, &arbitrary_tx(&state, &mut rng, 0.0));
+    }
+    let bills: Vec<Bill> = state.bills_sorted().into_iter().cloned().collect();
+    assert!(!bills.is_empty());
+    for bill in bills.iter() {
+        assert!(attempt_double_spend(&state, bill));
+    }
+}
+
+#[test]
+fn sm_5_sub_state_for_keeps_only_users_bills() {
+    let state = State::from([
+        Bill::new(User::Alice, 20, 0),
+        Bill::new(User::Bob, 5, 1),
+        Bill::new(User::Charlie, 8, 2),
+        Bill::new(User::Bob, 12, 3),
+    ]);
+    let bobs = state.sub_state_for(&User::Bob);
+    assert_eq!(
+        bobs.bills,
+        HashSet::from([Bill::new(User::Bob, 5, 1), Bill::new(User::Bob, 12, 3)])
+    );
+    assert_eq!(bobs.next_serial(), state.next_serial());
+    assert_eq!(bobs.balance_of(&User::Bob), 17);
+    assert_eq!(bobs.balance_of(&User::Alice), 0);
+    assert_eq!(bobs.total_supply(), 17);
+    assert_eq!(bobs.check_serial_consistency(), Ok(()));
+}
+
+#[test]
+fn sm_5_replayed_mint_is_ignored() {
+    let mint = CashTransaction::Mint {
+        minter: User::Alice,
+        amount: 20,
+    };
+    let start = ReplayProtectedState::from(State::new());
+    let once = ReplayProtectedDigitalCashSystem::next_state(&start, &mint);
+    assert!(once.has_seen(&tx_id(&mint)));
+    assert_eq!(
+        once.state().bills,
+        HashSet::from([Bill::new(User::Alice, 20, 0)])
+    );
+
+    let twice = ReplayProtectedDigitalCashSystem::next_state(&once, &mint);
+    assert_eq!(twice, once);
+
+    // a different mint is a different transaction
+    let other = CashTransaction::Mint {
+        minter: User::Alice,
+        amount: 21,
+    };
+    let end = ReplayProtectedDigitalCashSystem::next_state(&twice, &other);
+    assert_eq!(end.state().balance_of(&User::Alice), 41);
+}
+
+#[test]
+fn sm_5_replay_protection_repeats_ticks() {
+    let start = ReplayProtectedState::from(State::new());
+    let once = ReplayProtectedDigitalCashSystem::next_state(&start, &CashTransaction::Tick);
+    let twice = ReplayProtectedDigitalCashSystem::next_state(&once, &CashTransaction::Tick);
+    assert_eq!(twice.state().epoch(), 2);
+    assert!(!twice.has_seen(&tx_id(&CashTransaction::Tick)));
+
+    let nop = ReplayProtectedDigitalCashSystem::next_state(&twice, &CashTransaction::Nop);
+    let nop = ReplayProtectedDigitalCashSystem::next_state(&nop, &CashTransaction::Nop);
+    assert_eq!(nop, twice);
+}
+
+#[test]
+fn sm_5_replay_protection_repeats_admin_settings() {
+    let mut state = State::from([Bill::new(User::Alice, 20, 0)]);
+    state.set_pause_authority(User::Charlie);
+    let mut state = ReplayProtectedState::from(state);
+    let set_paused = |value| CashTransaction::SetPaused {
+        value,
+        authority: User::Charlie,
+    };
+    for value in [true, false, true] {
+        state = ReplayProtectedDigitalCashSystem::next_state(&state, &set_paused(value));
+    }
+    assert!(state.state().is_paused());
+    state = ReplayProtectedDigitalCashSystem::next_state(&state, &set_paused(false));
+    assert!(!state.state().is_paused());
+
+    let approve = |amount| CashTransaction::Approve {
+        owner: User::Alice,
+        spender: User::Bob,
+        amount,
+    };
+    for amount in [5, 0, 5] {
+        state = ReplayProtectedDigitalCashSystem::next_state(&state, &approve(amount));
+    }
+    assert_eq!(state.state().allowance(&User::Alice, &User::Bob), 5);
+}
+
+#[test]
+fn sm_5_purge_zero_value_bills() {
+    let mut state = State::from([
+        Bill::new(User::Alice, 20, 0),
+        Bill::new(User::Bob, 0, 1),
+        Bill::new(User::Charlie, 0, 2),
+    ]);
+    assert!(state.has_zero_value_bills());
+    assert_eq!(state.purge_zero_value(), 2);
+    assert!(!state.has_zero_value_bills());
+    assert_eq!(state.bills, HashSet::from([Bill::new(User::Alice, 20, 0)]));
+    assert_eq!(state.purge_zero_value(), 0);
+}
+
+#[test]
+fn sm_5_approved_spender_spends_within_allowance() {
+    let start = State::from([
+        Bill::new(User::Alice, 20, 0).with_condition(SpendCondition::Owned(User::Alice)),
+        Bill::new(User::Alice, 15, 1).with_condition(SpendCondition::Owned(User::Alice)),
+    ]);
+    let spend = CashTransaction::SignedTransfer {
+        chain_id: 0,
+        signers: vec![User::Bob],
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![Bill::new(User::Bob, 20, 2)],
+    };
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&start, &spend),
+        Err(TransferError::Unauthorized)
+    );
+
+    let approved = DigitalCashSystem::try_next_state(
+        &start,
+        &CashTransaction::Approve {
+            owner: User::Alice,
+            spender: User::Bob,
+            amount: 30,
+        },
+    )
+    .unwrap();
+    assert_eq!(approved.allowance(&User::Alice, &User::Bob), 30);
+
+    let end = DigitalCashSystem::try_next_state(&approved, &spend).unwrap();
+    assert_eq!(end.balance_of(&User::Bob), 20);
+    assert_eq!(end.allowance(&User::Alice, &User::Bob), 10);
+}
+
+#[test]
+fn sm_5_spend_beyond_allowance_fails() {
+    let start = State::from([
+        Bill::new(User::Alice, 20, 0).with_condition(SpendCondition::Owned(User::Alice)),
+        Bill::new(User::Alice, 15, 1).with_condition(SpendCondition::Owned(User::Alice)),
+    ]);
+    let approved = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::Approve {
+            owner: User::Alice,
+            spender: User::Bob,
+            amount: 30,
+        },
+    );
+    let spend_both = CashTransaction::SignedTransfer {
+        chain_id: 0,
+        signers: vec![User::Bob],
+        spends: vec![Bill::new(User::Alice, 20, 0), Bill::new(User::Alice, 15, 1)],
+        receives: vec![Bill::new(User::Bob, 35, 2)],
+    };
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&approved, &spend_both),
+        Err(TransferError::AllowanceExceeded {
+            available: 30,
+            requested: 35
+        })
+    );
+
+    // after a partial spend only the rest of the allowance is left
+    let partial = DigitalCashSystem::next_state(
+        &approved,
+        &CashTransaction::SignedTransfer {
+            chain_id: 0,
+            signers: vec![User::Bob],
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Bob, 20, 2)],
+        },
+    );
+    assert_eq!(partial.allowance(&User::Alice, &User::Bob), 10);
+    assert_eq!(
+        DigitalCashSystem::try_next_state(
+            &partial,
+            &CashTransaction::SignedTransfer {
+                chain_id: 0,
+                signers: vec![User::Bob],
+                spends: vec![Bill::new(User::Alice, 15, 1)],
+                receives: vec![Bill::new(User::Bob, 15, 3)],
+            },
+        ),
+        Err(TransferError::AllowanceExceeded {
+            available: 10,
+            requested: 15
+        })
+    );
+}
+
+#[test]
+fn sm_5_velocity_over_known_log() {
+    let genesis = State::from([Bill::new(User::Alice, 100, 0)]);
+    let txs = [
+        // 100 changes hands with a supply of 100
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 100, 0)],
+            receives: vec![Bill::new(User::Bob, 100, 1)],
+            memo: Vec::new(),
+        },
+        // nothing changes hands with a supply of 100
+        CashTransaction::Mint {
+            minter: User::Charlie,
+            amount: 50,
+        },
+        // 30 changes hands with a supply of 150, and 70 comes back as change
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Bob, 100, 1)],
+            receives: vec![Bill::new(User::Charlie, 30, 3), Bill::new(User::Bob, 70, 4)],
+            memo: Vec::new(),
+        },
+        // rejected: already spent
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Bob, 100, 1)],
+            receives: vec![Bill::new(User::Charlie, 100, 5)],
+            memo: Vec::new(),
+        },
+    ];
+    let expected = 130.0 / ((100.0 + 100.0 + 150.0) / 3.0);
+    assert!((velocity(&genesis, &txs) - expected).abs() < 1e-9);
+    assert_eq!(velocity(&genesis, &[]), 0.0);
+}
+
+#[test]
+fn sm_5_mint_and_send_gives_recipient_one_bill() {
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
+    let mint_and_send = CashTransaction::MintAndSend {
+        minter: User::Alice,
+        recipient: User::Bob,
+        amount: 15,
+    };
+    let end = DigitalCashSystem::try_next_state(&start, &mint_and_send).unwrap();
+    assert_eq!(
+        end.bills,
+        HashSet::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 15, 1)])
+    );
+    assert_eq!(end.next_serial(), 2);
+    assert_eq!(end.balance_of(&User::Alice), 20);
+}
+
+#[test]
+fn sm_5_state_one_mint_away_is_reachable() {
+    let from = State::from([Bill::new(User::Alice, 20, 0)]);
+    let to = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 10, 1)]);
+    assert!(is_reachable(&from, &to, 1));
+    assert!(is_reachable(&from, &from, 0));
+    assert!(!is_reachable(&from, &to, 0));
+}
+
+#[test]
+fn sm_5_state_needing_more_mints_than_depth_is_unreachable() {
+    let from = State::from([Bill::new(User::Alice, 20, 0)]);
+    let to = State::from([
+        Bill::new(User::Alice, 20, 0),
+        Bill::new(User::Bob, 10, 1),
+        Bill::new(User::Charlie, 30, 2),
+        Bill::new(User::Charlie, 30, 3),
+    ]);
+    assert!(!is_reachable(&from, &to, 2));
+    assert!(is_reachable(&from, &to, 3));
+}
+
+#[test]
+fn sm_5_rejection_log_tallies_errors() {
+    let start = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 10, 1)]);
+    let transactions = [
+        // rejected: nothing spent
+        CashTransaction::Transfer {
+            spends: vec![],
+            receives: vec![Bill::new(User::Bob, 20, 2)],
+            memo: Vec::new(),
+        },
+        // rejected: bill 7 does not exist
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 7)],
+            receives: vec![Bill::new(User::Bob, 20, 2)],
+            memo: Vec::new(),
+        },
+        // rejected: receives more than it spends
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Bob, 21, 2)],
+            memo: Vec::new(),
+        },
+        // rejected: nothing spent, again
+        CashTransaction::Transfer {
+            spends: vec![],
+            receives: vec![Bill::new(User::Charlie, 5, 2)],
+            memo: Vec::new(),
+        },
+        // applied, so not logged
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Bob, 20, 2)],
+            memo: Vec::new(),
+        },
+    ];
+
+    let mut log = RejectionLog::new();
+    let mut state = start;
+    for t in transactions.iter() {
+        state = DigitalCashSystem::next_state_observed(&state, t, &mut log);
+    }
+    assert_eq!(log.rejections().len(), 4);
+    assert_eq!(log.rejections()[1].summary, "Transfer touching [7]");
+    assert_eq!(log.rejections()[1].timestamp, 0);
+    assert_eq!(
+        log.summary(),
+        HashMap::from([
+            ("NoSpends", 2),
+            ("NonexistentBill", 1),
+            ("OutputsExceedInputs", 1),
+        ])
+    );
+}
+
+#[test]
+fn sm_5_balance_map_matches_balance_of() {
+    let state = State::from([
+        Bill::new(User::Alice, 20, 0),
+        Bill::new(User::Bob, 10, 1),
+        Bill::new(User::Alice, 5, 2),
+        Bill::new(User::Charlie, 7, 3),
+        Bill::new(User::Bob, 1, 4),
+    ]);
+    let balances = state.to_balance_map();
+    assert_eq!(
+        balances,
+        BTreeMap::from([(User::Alice, 25), (User::Bob, 11), (User::Charlie, 7)])
+    );
+    for (user, balance) in balances.iter() {
+        assert_eq!(*balance, state.balance_of(user));
+    }
+}
+
+#[test]
+fn sm_5_serial_base_numbers_first_bill() {
+    let start = State::new_with_serial_base(1000);
+    assert_eq!(start.highest_serial(), None);
+    let end = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::Mint {
+            minter: User::Alice,
+            amount: 20,
+        },
+    );
+    assert!(end.bills.contains(&Bill::new(User::Alice, 20, 1000)));
+    assert_eq!(end.next_serial(), 1001);
+    assert_eq!(end.highest_serial(), Some(1000));
+}
+
+#[test]
+fn sm_5_self_reshuffle_is_internal() {
+    let state = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 10, 1)]);
+    let t = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 10, 1)],
+        receives: vec![
+            Bill::new(User::Bob, 15, 2),
+            Bill::new(User::Alice, 10, 3),
+            Bill::new(User::Alice, 5, 4),
+        ],
+        memo: Vec::new(),
+    };
+    assert!(is_internal_reshuffle(&state, &t));
+}
+
+#[test]
+fn sm_5_paying_new_user_is_not_internal() {
+    let state = State::from([Bill::new(User::Alice, 20, 0)]);
+    let t = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![
+            Bill::new(User::Alice, 15, 1),
+            Bill::new(User::Charlie, 5, 2),
+        ],
+        memo: Vec::new(),
+    };
+    assert!(!is_internal_reshuffle(&state, &t));
+}
+
+#[test]
+fn sm_5_inclusion_proof_verifies() {
+    let state = State::from([
+        Bill::new(User::Alice, 20, 0),
+        Bill::new(User::Bob, 10, 1),
+        Bill::new(User::Charlie, 5, 2),
+        Bill::new(User::Alice, 7, 3),
+        Bill::new(User::Bob, 3, 4),
+    ]);
+    let root = state.merkle_root();
+    for bill in state.bills.iter() {
+        let proof = state.prove_inclusion(bill.serial).unwrap();
+        assert!(verify_inclusion(root, bill, &proof));
+    }
+
+    let proof = state.prove_inclusion(2).unwrap();
+    assert!(!verify_inclusion(
+        root,
+        &Bill::new(User::Charlie, 6, 2),
+        &proof
+    ));
+}
+
+#[test]
+fn sm_5_absent_serial_has_no_proof() {
+    let state = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 10, 1)]);
+    assert!(state.prove_inclusion(5).is_none());
+    assert_eq!(State::new().merkle_root(), [0; 32]);
+}
+
+#[test]
+fn sm_5_forbidden_recombination_allows_output_equal_to_largest_input() {
+    let mut start = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Alice, 10, 1)]);
+    start.set_forbid_recombination(true);
+    let end = DigitalCashSystem::try_next_state(
+        &start,
+        &CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0), Bill::new(User::Alice, 10, 1)],
+            receives: vec![Bill::new(User::Bob, 20, 2), Bill::new(User::Bob, 10, 3)],
+            memo: Vec::new(),
+        },
+    );
+    assert!(end.is_ok());
+}
+
+#[test]
+fn sm_5_forbidden_recombination_rejects_larger_output() {
+    let mut start = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Alice, 10, 1)]);
+    let t = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0), Bill::new(User::Alice, 10, 1)],
+        receives: vec![Bill::new(User::Bob, 30, 2)],
+        memo: Vec::new(),
+    };
+    assert!(DigitalCashSystem::try_next_state(&start, &t).is_ok());
+
+    start.set_forbid_recombination(true);
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&start, &t),
+        Err(TransferError::OutputExceedsInput {
+            largest_spent: 20,
+            received: 30,
+        })
+    );
+}
+
+#[test]
+fn sm_5_serial_diff_across_transfer() {
+    let start = State::from([
+        Bill::new(User::Alice, 20, 0),
+        Bill::new(User::Bob, 10, 1),
+        Bill::new(User::Alice, 5, 2),
+    ]);
+    let end = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0), Bill::new(User::Alice, 5, 2)],
+            receives: vec![Bill::new(User::Bob, 15, 3), Bill::new(User::Alice, 10, 4)],
+            memo: Vec::new(),
+        },
+    );
+    assert_eq!(start.serial_diff(&end), (vec![0, 2], vec![3, 4]));
+    assert_eq!(end.serial_diff(&start), (vec![3, 4], vec![0, 2]));
+    assert_eq!(start.serial_diff(&start), (vec![], vec![]));
+}
+
+#[test]
+fn sm_5_cap_balance_burns_excess() {
+    let mut start = State::from([
+        Bill::new(User::Alice, 20, 0),
+        Bill::new(User::Alice, 10, 1),
+        Bill::new(User::Alice, 5, 2),
+        Bill::new(User::Bob, 10, 3),
+    ]);
+    start.set_pause_authority(User::Charlie);
+    let t = CashTransaction::CapBalance {
+        user: User::Alice,
+        cap: 20,
+        authority: User::Charlie,
+    };
+    let end = DigitalCashSystem::next_state(&start, &t);
+    assert_eq!(end.balance_of(&User::Alice), 20);
+    assert!(end.bills.contains(&Bill::new(User::Alice, 20, 0)));
+    assert_eq!(end.balance_of(&User::Bob), 10);
+    assert_eq!(end.total_burned(), 15);
+
+    assert_eq!(
+        DigitalCashSystem::try_next_state(
+            &start,
+            &CashTransaction::CapBalance {
+                user: User::Alice,
+                cap: 20,
+                authority: User::Alice,
+            },
+        ),
+        Err(TransferError::Unauthorized)
+    );
+}
+
+#[test]
+fn sm_5_cap_balance_leaves_user_under_cap() {
+    let mut start = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 10, 1)]);
+    start.set_pause_authority(User::Charlie);
+    let end = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::CapBalance {
+            user: User::Bob,
+            cap: 15,
+            authority: User::Charlie,
+        },
+    );
+    assert_eq!(end, start);
+}
+
+#[test]
+fn sm_5_cap_balance_ignores_shares() {
+    let mut start = State::from([
+        Bill::new(User::Alice, 30, 0),
+        Bill::new(User::Bob, 30, 1),
+        Bill::new(User::Alice, 20, 2),
+        Bill::new(User::Alice, 10, 3),
+    ]);
+    start.set_pause_authority(User::Charlie);
+    let shared = DigitalCashSystem::try_next_state(
+        &start,
+        &CashTransaction::ShareBills {
+            spends: vec![Bill::new(User::Alice, 30, 0), Bill::new(User::Bob, 30, 1)],
+            shares: vec![(User::Alice, 30), (User::Bob, 30)],
+        },
+    )
+    .unwrap();
+    let capped = |cap| {
+        DigitalCashSystem::try_next_state(
+            &shared,
+            &CashTransaction::CapBalance {
+                user: User::Alice,
+                cap,
+                authority: User::Charlie,
+            },
+        )
+        .unwrap()
+    };
+
+    // only the bills are capped, the shares stay as they are
+    let end = capped(20);
+    assert!(end.bills.contains(&Bill::new(User::Alice, 20, 2)));
+    assert_eq!(end.balance_of(&User::Alice), 50);
+    assert_eq!(end.total_burned(), 10);
+
+    let end = capped(30);
+    assert_eq!(end.balance_of(&User::Alice), 60);
+    assert_eq!(end.total_burned(), 0);
+}
+
+#[test]
+fn sm_5_streaming_ends_at_one_shot_state() {
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
+    let txs = [
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Bob, 15, 1), Bill::new(User::Alice, 5, 2)],
+            memo: Vec::new(),
+        },
+        CashTransaction::Mint {
+            minter: User::Charlie,
+            amount: 7,
+        },
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Bob, 15, 1)],
+            receives: vec![Bill::new(User::Charlie, 15, 4)],
+            memo: Vec::new(),
+        },
+    ];
+
+    let stream: Vec<State> = apply_streaming(start.clone(), &txs).collect();
+    assert_eq!(stream.len(), 3);
+    assert_eq!(stream[0].balance_of(&User::Bob), 15);
+    assert_eq!(stream[1].balance_of(&User::Charlie), 7);
+    let one_shot = txs
+        .iter()
+        .fold(start, |state, t| DigitalCashSystem::next_state(&state, t));
+    assert_eq!(stream.last(), Some(&one_shot));
+}
+
+#[test]
+fn sm_5_reserial_preserves_bills() {
+    let start = State::from([
+        Bill::new(User::Alice, 20, 3),
+        Bill::new(User::Bob, 10, 7),
+        Bill::new(User::Charlie, 5, 12),
+    ]);
+    let (state, mapping) = start.reserial();
+
+    assert_eq!(mapping, HashMap::from([(3, 0), (7, 1), (12, 2)]));
+    let new_serials: HashSet<u64> = mapping.values().copied().collect();
+    assert_eq!(new_serials.len(), mapping.len());
+    for bill in start.bills.iter() {
+        assert!(state
+            .bills
+            .contains(&Bill::new(bill.owner, bill.amount, mapping[&bill.serial])));
+    }
+    assert_eq!(state.bills.len(), 3);
+    assert_eq!(state.next_serial(), 3);
+    assert_eq!(state.check_serial_consistency(), Ok(()));
+    for user in User::ALL {
+        assert_eq!(state.balance_of(&user), start.balance_of(&user));
+    }
+}
+
+#[test]
+fn sm_5_max_conserving_transfer_under_output_cap() {
+    let state = State::from([
+        Bill::new(User::Alice, 600_000_000_000, 0),
+        Bill::new(User::Alice, 500_000_000_000, 1),
+        Bill::new(User::Alice, 300_000_000_000, 2),
+        Bill::new(User::Alice, 10, 3),
+        Bill::new(User::Bob, 10, 4),
+    ]);
+
+    let (spends, value) = state.max_conserving_transfer(&User::Alice, 1).unwrap();
+    assert_eq!(value, 900_000_000_010);
+    let serials: Vec<u64> = spends.iter().map(|bill| bill.serial).collect();
+    assert_eq!(serials, vec![0, 2, 3]);
+
+    let (spends, value) = state.max_conserving_transfer(&User::Alice, 2).unwrap();
+    assert_eq!(value, 1_400_000_000_010);
+    assert_eq!(spends.len(), 4);
+
+    assert!(state.max_conserving_transfer(&User::Charlie, 1).is_none());
+    assert!(state.max_conserving_transfer(&User::Alice, 0).is_none());
+}
+
+#[test]
+fn sm_5_max_conserving_transfer_with_many_bills() {
+    let state: State = (0..5_000)
+        .map(|i| Bill::new(User::Alice, i + 1, i))
+        .collect();
+    let (spends, value) = state.max_conserving_transfer(&User::Alice, 1).unwrap();
+    assert_eq!(value, 5_000 + 4_999 + 4_998 + 4_997);
+    let serials: Vec<u64> = spends.iter().map(|bill| bill.serial).collect();
+    assert_eq!(serials, vec![4_996, 4_997, 4_998, 4_999]);
+}
+
+#[test]
+fn sm_5_nop_changes_nothing() {
+    let mut start = State::from([Bill::new(User::Alice, 20, 0)]);
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&start, &CashTransaction::Nop),
+        Ok(start.clone())
+    );
+
+    start.set_pause_authority(User::Charlie);
+    let paused = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::SetPaused {
+            value: true,
+            authority: User::Charlie,
+        },
+    );
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&paused, &CashTransaction::Nop),
+        Ok(paused.clone())
+    );
+}
+
+#[test]
+fn sm_5_nop_succeeds_in_apply_each() {
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
+    let txs = [
+        CashTransaction::Nop,
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Bob, 20, 1)],
+            memo: Vec::new(),
+        },
+        CashTransaction::Nop,
+        // rejected: already spent
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Bob, 20, 2)],
+            memo: Vec::new(),
+        },
+    ];
+    let (end, outcomes) = apply_each(&start, &txs);
+    assert_eq!(outcomes[0], Ok(()));
+    assert_eq!(outcomes[1], Ok(()));
+    assert_eq!(outcomes[2], Ok(()));
+    assert!(outcomes[3].is_err());
+    assert_eq!(end.balance_of(&User::Bob), 20);
+    assert_eq!(end.total_supply(), 20);
+}
+
+#[test]
+fn sm_5_pack_block_includes_nops_last() {
+    let state = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 20, 1)]);
+    let pool = vec![
+        CashTransaction::Nop,
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Charlie, 15, 2)],
+            memo: Vec::new(),
+        },
+        CashTransaction::Nop,
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Bob, 20, 1)],
+            receives: vec![Bill::new(User::Charlie, 20, 3)],
+            memo: Vec::new(),
+        },
+        CashTransaction::Tick,
+    ];
+    assert_eq!(pack_block(&state, &pool, 100), vec![1, 3, 4, 0, 2]);
+}
+
+#[test]
+fn sm_5_bill_age_counts_epochs_since_mint() {
+    let mut state = DigitalCashSystem::next_state(
+        &State::new(),
+        &CashTransaction::Mint {
+            minter: User::Alice,
+            amount: 20,
+        },
+    );
+    assert_eq!(state.bill_age(0), Some(0));
+    for _ in 0..5 {
+        state = DigitalCashSystem::next_state(&state, &CashTransaction::Tick);
+    }
+    assert_eq!(state.bill_age(0), Some(5));
+    assert_eq!(state.bill_age(1), None);
+
+    let state = DigitalCashSystem::next_state(
+        &state,
+        &CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Bob, 20, 1)],
+            memo: Vec::new(),
+        },
+    );
+    assert_eq!(state.bill_age(1), Some(0));
+}
+
+#[test]
+fn sm_5_demurrage_decays_held_bills() {
+    let mut state = State::from([Bill::new(User::Alice, 10_000, 0)]);
+    state.set_pause_authority(User::Charlie);
+    for _ in 0..5 {
+        state = DigitalCashSystem::next_state(&state, &CashTransaction::Tick);
+    }
+    let t = CashTransaction::ApplyDemurrage {
+        rate_per_epoch: 100,
+        authority: User::Charlie,
+    };
+    let (state, events) = DigitalCashSystem::apply_with_events(&state, &t).unwrap();
+    assert!(state.bills.contains(&Bill::new(User::Alice, 9_500, 0)));
+    assert_eq!(state.total_burned(), 500);
+    assert_eq!(
+        events,
+        vec![CashEvent::BillDecayed {
+            serial: 0,
+            amount: 500,
+        }]
+    );
+
+    // the epochs already charged for are not charged again
+    assert_eq!(DigitalCashSystem::next_state(&state, &t), state);
+}
+
+#[test]
+fn sm_5_demurrage_removes_fully_decayed_bill() {
+    let mut state = State::from([Bill::new(User::Alice, 30, 0), Bill::new(User::Bob, 10, 1)]);
+    state.set_pause_authority(User::Charlie);
+    for _ in 0..2 {
+        state = DigitalCashSystem::next_state(&state, &CashTransaction::Tick);
+    }
+    let state = DigitalCashSystem::next_state(
+        &state,
+        &CashTransaction::ApplyDemurrage {
+            rate_per_epoch: 6_000,
+            authority: User::Charlie,
+        },
+    );
+    assert!(state.bills.is_empty());
+    assert_eq!(state.total_burned(), 40);
+}
+
+#[test]
+fn sm_5_demurrage_saturates_at_extreme_rates() {
+    let mut state = State::from([Bill::new(User::Alice, u64::MAX, 0)]);
+    state.set_pause_authority(User::Charlie);
+    for _ in 0..2 {
+        state = DigitalCashSystem::next_state(&state, &CashTransaction::Tick);
+    }
+    let state = DigitalCashSystem::next_state(
+        &state,
+        &CashTransaction::ApplyDemurrage {
+            rate_per_epoch: u64::MAX,
+            authority: User::Charlie,
+        },
+    );
+    assert!(state.bills.is_empty());
+    assert_eq!(state.total_burned(), u64::MAX);
+}
+
+#[test]
+fn sm_5_demurrage_needs_authority() {
+    let mut state = State::from([Bill::new(User::Alice, 30, 0)]);
+    state = DigitalCashSystem::next_state(&state, &CashTransaction::Tick);
+    let wipe = |authority| CashTransaction::ApplyDemurrage {
+        rate_per_epoch: BASIS_POINTS,
+        authority,
+    };
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&state, &wipe(User::Alice)),
+        Err(TransferError::Unauthorized)
+    );
+
+    state.set_pause_authority(User::Charlie);
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&state, &wipe(User::Bob)),
+        Err(TransferError::Unauthorized)
+    );
+    let end = DigitalCashSystem::try_next_state(&state, &wipe(User::Charlie)).unwrap();
+    assert_eq!(end.total_supply(), 0);
+}
+
+#[test]
+fn sm_5_creator_of_finds_creating_transaction() {
+    let log = [
+        CashTransaction::Mint {
+            minter: User::Alice,
+            amount: 20,
+        },
+        CashTransaction::Mint {
+            minter: User::Bob,
+            amount: 10,
+        },
+        // rejected: receives more than it spends
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Charlie, 25, 2)],
+            memo: Vec::new(),
+        },
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![
+                Bill::new(User::Charlie, 15, 2),
+                Bill::new(User::Alice, 5, 3),
+            ],
+            memo: Vec::new(),
+        },
+    ];
+    assert_eq!(creator_of(&log, 1), Some(1));
+    assert_eq!(creator_of(&log, 3), Some(3));
+    assert_eq!(creator_of(&log, 0), Some(0));
+    assert_eq!(creator_of(&log, 9), None);
+}
+
+#[test]
+fn sm_5_compact_transfer_round_trips() {
+    let state = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 10, 1)]);
+    let t = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 10, 1)],
+        receives: vec![
+            Bill::new(User::Charlie, 25, 2),
+            Bill::new(User::Alice, 5, 3),
+        ],
+        memo: b"rent".to_vec(),
+    };
+    let compact = t.to_compact().unwrap();
+    assert_eq!(
+        compact,
+        CompactTransfer {
+            inputs: vec![0, 1],
+            outputs: vec![(User::Charlie, 25), (User::Alice, 5)],
+            memo: b"rent".to_vec(),
+        }
+    );
+    let expanded = CashTransaction::from_compact(&state, &compact).unwrap();
+    assert_eq!(tx_id(&expanded), tx_id(&t));
+    assert!(CashTransaction::Tick.to_compact().is_none());
+}
+
+#[test]
+fn sm_5_compact_transfer_unknown_input_fails() {
+    let state = State::from([Bill::new(User::Alice, 20, 0)]);
+    let compact = CompactTransfer {
+        inputs: vec![0, 4],
+        outputs: vec![(User::Bob, 20)],
+        memo: Vec::new(),
+    };
+    assert!(CashTransaction::from_compact(&state, &compact).is_none());
+}
+
+#[test]
+fn sm_5_window_spend_limit_blocks_then_recovers() {
+    let half = MAX_WINDOW_SPEND / 2;
+    let mut state = State::from([
+        Bill::new(User::Alice, half, 0),
+        Bill::new(User::Alice, half, 1),
+        Bill::new(User::Alice, half, 2),
+    ]);
+    state.set_limit_window_spend(true);
+    let pay = |serial: u64, next_serial: u64| CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, half, serial)],
+        receives: vec![Bill::new(User::Bob, half, next_serial)],
+        memo: Vec::new(),
+    };
+
+    // spending up to the limit is fine
+    state = DigitalCashSystem::try_next_state(&state, &pay(0, 3)).unwrap();
+    state = DigitalCashSystem::next_state(&state, &CashTransaction::Tick);
+    state = DigitalCashSystem::try_next_state(&state, &pay(1, 4)).unwrap();
+
+    // going past it is not
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&state, &pay(2, 5)),
+        Err(TransferError::WindowSpendExceeded {
+            available: 0,
+            requested: half,
+        })
+    );
+
+    // until the first spend leaves the window
+    for _ in 0..SPEND_WINDOW - 1 {
+        state = DigitalCashSystem::next_state(&state, &CashTransaction::Tick);
+    }
+    assert!(DigitalCashSystem::try_next_state(&state, &pay(2, 5)).is_ok());
+}
+
+#[test]
+fn sm_5_window_spend_ignores_change() {
+    let mut state = State::from([Bill::new(User::Alice, MAX_WINDOW_SPEND * 2, 0)]);
+    state.set_limit_window_spend(true);
+    let t = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, MAX_WINDOW_SPEND * 2, 0)],
+        receives: vec![
+            Bill::new(User::Bob, MAX_WINDOW_SPEND, 1),
+            Bill::new(User::Alice, MAX_WINDOW_SPEND, 2),
+        ],
+        memo: Vec::new(),
+    };
+    assert!(DigitalCashSystem::try_next_state(&state, &t).is_ok());
+}
+
+#[test]
+fn sm_5_over_collateralized_is_solvent() {
+    let state = State::from([Bill::new(User::Alice, 30, 0), Bill::new(User::Bob, 20, 1)]);
+    assert!(is_solvent(&state, 40));
+    assert_eq!(reserve_ratio(&state, 40), 1.25);
+}
+
+#[test]
+fn sm_5_exactly_collateralized_is_solvent() {
+    let state = State::from([Bill::new(User::Alice, 30, 0), Bill::new(User::Bob, 20, 1)]);
+    assert!(is_solvent(&state, 50));
+    assert_eq!(reserve_ratio(&state, 50), 1.0);
+}
+
+#[test]
+fn sm_5_under_collateralized_is_insolvent() {
+    let state = State::from([Bill::new(User::Alice, 30, 0), Bill::new(User::Bob, 20, 1)]);
+    assert!(!is_solvent(&state, 100));
+    assert_eq!(reserve_ratio(&state, 100), 0.5);
+    assert_eq!(reserve_ratio(&state, 0), f64::INFINITY);
+}
+
+#[test]
+fn sm_5_validate_outputs_returns_change() {
+    assert_eq!(
+        validate_outputs(50, &[(User::Bob, 20), (User::Charlie, 25)]),
+        Ok(5)
+    );
+    assert_eq!(
+        validate_outputs(45, &[(User::Bob, 20), (User::Charlie, 25)]),
+        Ok(0)
+    );
+}
+
+#[test]
+fn sm_5_validate_outputs_rejects_over_allocation() {
+    assert_eq!(
+        validate_outputs(40, &[(User::Bob, 20), (User::Charlie, 25)]),
+        Err(BuildError::OverAllocated {
+            available: 40,
+            requested: 45,
+        })
+    );
+    assert_eq!(validate_outputs(40, &[]), Err(BuildError::NoOutputs));
+    assert_eq!(
+        validate_outputs(40, &[(User::Bob, 0)]),
+        Err(BuildError::ZeroAmountOutput)
+    );
+}
+
+#[test]
+fn sm_5_all_change_combinations_finds_every_subset() {
+    let state = State::from([
+        Bill::new(User::Alice, 2, 0),
+        Bill::new(User::Alice, 2, 1),
+        Bill::new(User::Alice, 4, 2),
+        Bill::new(User::Bob, 4, 3),
+    ]);
+    let combinations = state.all_change_combinations(&User::Alice, 4);
+    assert_eq!(combinations.len(), 2);
+    assert!(combinations.contains(&vec![
+        Bill::new(User::Alice, 2, 0),
+        Bill::new(User::Alice, 2, 1)
+    ]));
+    assert!(combinations.contains(&vec![Bill::new(User::Alice, 4, 2)]));
+    assert!(state.all_change_combinations(&User::Alice, 5).is_empty());
+}
+
+#[test]
+fn sm_5_issuance_schedule_mints_on_tick() {
+    let mut state = State::from([Bill::new(User::Alice, 20, 0)]);
+    state.set_issuance_schedule(IssuanceSchedule {
+        treasury: User::Charlie,
+        per_epoch: 100,
+    });
+    for _ in 0..4 {
+        state = DigitalCashSystem::next_state(&state, &CashTransaction::Tick);
+    }
+    assert_eq!(state.balance_of(&User::Charlie), 400);
+    assert_eq!(state.total_supply(), 420);
+    assert_eq!(state.next_serial(), 5);
+}
+
+#[test]
+fn sm_5_issuance_above_bill_cap_splits() {
+    let mut state = State::new();
+    state.set_issuance_schedule(IssuanceSchedule {
+        treasury: User::Bob,
+        per_epoch: MAX_BILL_AMOUNT * 2 + 1,
+    });
+    let state = DigitalCashSystem::next_state(&state, &CashTransaction::Tick);
+    assert_eq!(state.bills.len(), 3);
+    assert_eq!(state.total_supply(), MAX_BILL_AMOUNT * 2 + 1);
+}
+
+#[test]
+fn sm_5_issuance_overflowing_supply_fails() {
+    let mut state = State::from([Bill::new(User::Alice, u64::MAX - 150, 0)]);
+    state.set_issuance_schedule(IssuanceSchedule {
+        treasury: User::Bob,
+        per_epoch: 100,
+    });
+    let state = DigitalCashSystem::next_state(&state, &CashTransaction::Tick);
+    assert_eq!(state.total_supply(), u64::MAX - 50);
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&state, &CashTransaction::Tick),
+        Err(TransferError::SupplyOverflow)
+    );
+    assert_eq!(state.epoch(), 1);
+}
+
+#[test]
+fn sm_5_validation_cost_grows_linearly() {
+    let n: u64 = 200;
+    let state: State = (0..n).map(|i| Bill::new(User::Alice, 1, i)).collect();
+    let t = CashTransaction::Transfer {
+        spends: (0..n).map(|i| Bill::new(User::Alice, 1, i)).collect(),
+        receives: vec![Bill::new(User::Bob, n, n)],
+        memo: Vec::new(),
+    };
+    let (result, comparisons) = validate_instrumented(&state, &t);
+    assert_eq!(result, Ok(()));
+
+    // checking every pair of spends for duplicates would take n * (n - 1) / 2 comparisons
+    // on its own, where the set based check takes one lookup per spend
+    let n = n as usize;
+    assert!(comparisons < n * (n - 1) / 2);
+    assert_eq!(comparisons, 4 * n);
+}
+
+#[test]
+fn sm_5_rotate_key_moves_all_bills() {
+    let start = State::from([
+        Bill::new(User::Charlie, 20, 0),
+        Bill::new(User::Bob, 10, 1),
+        Bill::new(User::Charlie, 5, 2),
+    ]);
+    let end = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::RotateKey {
+            old: User::Charlie,
+            new: User::Alice,
+            signer: User::Charlie,
+        },
+    );
+    let expected = State::from([
+        Bill::new(User::Alice, 20, 0),
+        Bill::new(User::Bob, 10, 1),
+        Bill::new(User::Alice, 5, 2),
+    ]);
+    assert_eq!(end, expected);
+    assert_eq!(end.balance_of(&User::Charlie), 0);
+}
+
+#[test]
+fn sm_5_rotate_key_needs_old_key() {
+    let start = State::from([Bill::new(User::Charlie, 20, 0)]);
+    assert_eq!(
+        DigitalCashSystem::try_next_state(
+            &start,
+            &CashTransaction::RotateKey {
+                old: User::Charlie,
+                new: User::Bob,
+                signer: User::Bob,
+            },
+        ),
+        Err(TransferError::Unauthorized)
+    );
+}
+
+#[test]
+fn sm_5_rotate_key_moves_conditions_and_escrows() {
+    let start = State::from([
+        Bill::new(User::Charlie, 20, 0).with_condition(SpendCondition::Owned(User::Charlie)),
+        Bill::new(User::Alice, 10, 1)
+            .with_condition(SpendCondition::AnyOf(vec![User::Alice, User::Charlie])),
+        Bill::new(User::Charlie, 5, 2),
+    ]);
+    let escrowed = DigitalCashSystem::try_next_state(
+        &start,
+        &CashTransaction::Escrow {
+            bill: Bill::new(User::Charlie, 5, 2),
+            claimant: User::Alice,
+            secret_hash: hash32(b"secret"),
+        },
+    )
+    .unwrap();
+    let rotated = DigitalCashSystem::try_next_state(
+        &escrowed,
+        &CashTransaction::RotateKey {
+            old: User::Charlie,
+            new: User::Bob,
+            signer: User::Charlie,
+        },
+    )
+    .unwrap();
+
+    let spend = |signer, bill: Bill| CashTransaction::SignedTransfer {
+        chain_id: 0,
+        signers: vec![signer],
+        receives: vec![Bill::new(User::Alice, bill.amount, 3)],
+        spends: vec![bill],
+    };
+    let by_bob = spend(User::Bob, Bill::new(User::Bob, 20, 0));
+    assert!(DigitalCashSystem::try_next_state(&rotated, &by_bob).is_ok());
+    let by_charlie = spend(User::Charlie, Bill::new(User::Bob, 20, 0));
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&rotated, &by_charlie),
+        Err(TransferError::Unauthorized)
+    );
+    let shared_with_bob = spend(User::Bob, Bill::new(User::Alice, 10, 1));
+    assert!(DigitalCashSystem::try_next_state(&rotated, &shared_with_bob).is_ok());
+
+    // the escrowed bill goes back to the new key once it times out
+    let mut state = rotated;
+    for _ in 0..ESCROW_TIMEOUT {
+        state = DigitalCashSystem::next_state(&state, &CashTransaction::Tick);
+    }
+    let reclaimed =
+        DigitalCashSystem::try_next_state(&state, &CashTransaction::ReclaimEscrow { serial: 2 })
+            .unwrap();
+    assert_eq!(reclaimed.balance_of(&User::Bob), 25);
+    assert_eq!(reclaimed.balance_of(&User::Charlie), 0);
+}
+
+#[test]
+fn sm_5_rotate_key_into_holder_fails() {
+    let mut start = State::from([Bill::new(User::Alice, 1_000, 0), Bill::new(User::Bob, 1, 1)]);
+    start.set_transfer_tax(true);
+    start.set_limit_window_spend(true);
+    assert_eq!(
+        DigitalCashSystem::try_next_state(
+            &start,
+            &CashTransaction::RotateKey {
+                old: User::Alice,
+                new: User::Bob,
+                signer: User::Alice,
+            },
+        ),
+        Err(TransferError::KeyInUse)
+    );
+}
+
+#[test]
+fn sm_5_escrow_is_valid_without_warning() {
+    let state = State::from([Bill::new(User::Alice, 30, 0)]);
+    let t = CashTransaction::Escrow {
+        bill: Bill::new(User::Alice, 30, 0),
+        claimant: User::Bob,
+        secret_hash: hash32(b"secret"),
+    };
+    assert_eq!(validate_with_warnings(&state, &t), (Ok(()), Vec::new()));
+}
+
+#[test]
+fn sm_5_burning_transfer_is_valid_with_warning() {
+    let state = State::from([Bill::new(User::Alice, 100, 0)]);
+    let t = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 100, 0)],
+        receives: vec![Bill::new(User::Bob, 60, 1), Bill::new(User::Alice, 5, 2)],
+        memo: Vec::new(),
+    };
+    assert_eq!(
+        validate_with_warnings(&state, &t),
+        (
+            Ok(()),
+            vec![Warning::ValueWillBurn(35), Warning::CreatesDust(5)]
+        )
+    );
+
+    let rejected = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 100, 0)],
+        receives: vec![Bill::new(User::Bob, 101, 1)],
+        memo: Vec::new(),
+    };
+    let (result, warnings) = validate_with_warnings(&state, &rejected);
+    assert!(result.is_err());
+    assert!(warnings.is_empty());
+}
+
+#[test]
+fn sm_5_total_fees_sums_batch() {
+    let txs = [
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Bob, 17, 3)],
+            memo: Vec::new(),
+        },
+        CashTransaction::Mint {
+            minter: User::Charlie,
+            amount: 50,
+        },
+        CashTransaction::SignedTransfer {
+            chain_id: 0,
+            spends: vec![Bill::new(User::Bob, 10, 1)],
+            receives: vec![Bill::new(User::Alice, 5, 4)],
+            signers: vec![User::Bob],
+        },
+        CashTransaction::Tick,
+    ];
+    assert_eq!(total_fees(&txs), 8);
+    assert_eq!(total_fees(&txs), txs.iter().map(fee_for).sum::<u64>());
+    assert_eq!(total_fees(&[]), 0);
+}
+
+#[test]
+fn sm_5_total_fees_saturates() {
+    let expensive = || CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, u64::MAX / 2, 0)],
+        receives: vec![Bill::new(User::Bob, 1, 1)],
+        memo: Vec::new(),
+    };
+    let txs: Vec<CashTransaction> = (0..4).map(|_| expensive()).collect();
+    assert_eq!(total_fees(&txs), u64::MAX);
+}
+
+#[test]
+fn sm_5_minimal_repro_keeps_rejection() {
+    let state = State::from([
+        Bill::new(User::Alice, 20, 0),
+        Bill::new(User::Bob, 10, 1),
+        Bill::new(User::Charlie, 5, 2),
+        Bill::new(User::Alice, 7, 3),
+    ]);
+    let t = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 10, 9)],
+        receives: vec![Bill::new(User::Charlie, 30, 4)],
+        memo: Vec::new(),
+    };
+    let repro = minimal_repro(&state, &t);
+    assert_eq!(repro.bills_sorted(), vec![&Bill::new(User::Alice, 20, 0)]);
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&repro, &t).map(|_| ()),
+        Err(TransferError::NonexistentBill { serial: 9 })
+    );
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&state, &t).map(|_| ()),
+        Err(TransferError::NonexistentBill { serial: 9 })
+    );
+}
+
+#[test]
+fn sm_5_fanout_at_limit_passes() {
+    let state = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 20, 1)]);
+    let fanout = 2 * FANOUT_FACTOR as u64;
+    let t = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 20, 1)],
+        receives: (0..fanout)
+            .map(|i| Bill::new(User::Charlie, 1, 2 + i))
+            .collect(),
+        memo: Vec::new(),
+    };
+    assert!(DigitalCashSystem::try_next_state(&state, &t).is_ok());
+}
+
+#[test]
+fn sm_5_fanout_past_limit_fails() {
+    let state = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 20, 1)]);
+    let fanout = 2 * FANOUT_FACTOR as u64 + 1;
+    let t = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 20, 1)],
+        receives: (0..fanout)
+            .map(|i| Bill::new(User::Charlie, 1, 2 + i))
+            .collect(),
+        memo: Vec::new(),
+    };
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&state, &t),
+        Err(TransferError::ExcessiveFanout)
+    );
+}
+
+#[test]
+fn sm_5_accounting_breakdown_balances_with_fee_and_change() {
+    let state = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Alice, 15, 1)]);
+    let t = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0), Bill::new(User::Alice, 15, 1)],
+        receives: vec![Bill::new(User::Bob, 25, 2), Bill::new(User::Alice, 7, 3)],
+        memo: Vec::new(),
+    };
+    let breakdown = accounting_breakdown(&state, &t).unwrap();
+    assert_eq!(
+        breakdown,
+        AccountingBreakdown {
+            spent: 35,
+            received: 32,
+            fee: 3,
+            burned: 0,
+        }
+    );
+    assert_eq!(
+        breakdown.spent,
+        breakdown.received + breakdown.fee + breakdown.burned
+    );
+}
+
+#[test]
+fn sm_5_accounting_breakdown_counts_pooled_surplus() {
+    let state = State::from([Bill::new(User::Alice, 20, 0)]);
+    let t = CashTransaction::PooledTransfer {
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![Bill::new(User::Bob, 16, 1)],
+    };
+    assert_eq!(
+        accounting_breakdown(&state, &t),
+        Some(AccountingBreakdown {
+            spent: 20,
+            received: 16,
+            fee: 0,
+            burned: 4,
+        })
+    );
+    assert!(accounting_breakdown(&state, &CashTransaction::Tick).is_none());
+}
+
+#[test]
+fn sm_5_contended_serials_maps_competing_spends() {
+    let pending = [
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Bob, 20, 3)],
+            memo: Vec::new(),
+        },
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Bob, 10, 1)],
+            receives: vec![Bill::new(User::Charlie, 10, 3)],
+            memo: Vec::new(),
+        },
+        CashTransaction::Transfer {
+            spends: vec![
+                Bill::new(User::Alice, 20, 0),
+                Bill::new(User::Charlie, 5, 2),
+            ],
+            receives: vec![Bill::new(User::Charlie, 25, 3)],
+            memo: Vec::new(),
+        },
+    ];
+    assert_eq!(
+        contended_serials(&pending),
+        HashMap::from([(0, vec![0, 2])])
+    );
+}
+
+#[test]
+fn sm_5_contended_serials_ignores_distinct_co_owner_exits() {
+    let exit = |shareholder| CashTransaction::ExitShare {
+        serial: 2,
+        shareholder,
+    };
+    assert!(contended_serials(&[exit(User::Alice), exit(User::Bob)]).is_empty());
+    assert_eq!(
+        contended_serials(&[
+            exit(User::Alice),
+            exit(User::Bob),
+            exit(User::Bob),
+            exit(User::Alice),
+        ]),
+        HashMap::from([(2, vec![0, 1, 2, 3])])
+    );
+}
+
+#[test]
+fn sm_5_strict_order_accepts_ascending_receives() {
+    let mut state = State::from([Bill::new(User::Alice, 20, 0)]);
+    state.set_strict_receive_order(true);
+    let t = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![Bill::new(User::Bob, 15, 1), Bill::new(User::Alice, 5, 2)],
+        memo: Vec::new(),
+    };
+    assert!(DigitalCashSystem::try_next_state(&state, &t).is_ok());
+}
+
+#[test]
+fn sm_5_strict_order_rejects_descending_receives() {
+    let mut state = State::from([Bill::new(User::Alice, 20, 0)]);
+    let t = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![Bill::new(User::Alice, 5, 2), Bill::new(User::Bob, 15, 1)],
+        memo: Vec::new(),
+    };
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&state, &t),
+        Err(TransferError::NonContiguousSerial {
+            expected: 1,
+            got: 2,
+        })
+    );
+
+    state.set_strict_receive_order(true);
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&state, &t),
+        Err(TransferError::UnorderedReceives)
+    );
+}
+
+#[test]
+fn sm_5_anonymity_set_counts_same_denomination() {
+    let state = State::from([
+        Bill::new(User::Alice, 10, 0),
+        Bill::new(User::Bob, 10, 1),
+        Bill::new(User::Charlie, 10, 2),
+        Bill::new(User::Alice, 10, 3),
+        Bill::new(User::Bob, 7, 4),
+    ]);
+    assert_eq!(state.anonymity_set(0), 3);
+    assert_eq!(state.anonymity_set(2), 3);
+    assert_eq!(state.anonymity_set(4), 0);
+    assert_eq!(state.anonymity_set(9), 0);
+}
+
+#[test]
+fn sm_5_taxed_transfer_leaving_post_tax_amount_passes() {
+    let mut state = State::from([Bill::new(User::Alice, 1_000, 0)]);
+    state.set_transfer_tax(true);
+    let t = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 1_000, 0)],
+        receives: vec![Bill::new(User::Bob, 990, 1)],
+        memo: Vec::new(),
+    };
+    let end = DigitalCashSystem::try_next_state(&state, &t).unwrap();
+    assert_eq!(end.balance_of(&User::Bob), 990);
+    assert_eq!(end.total_burned(), 10);
+    assert_eq!(
+        accounting_breakdown(&state, &t),
+        Some(AccountingBreakdown {
+            spent: 1_000,
+            received: 990,
+            fee: 0,
+            burned: 10,
+        })
+    );
+}
+
+#[test]
+fn sm_5_taxed_transfer_leaving_too_much_fails() {
+    let mut state = State::from([Bill::new(User::Alice, 1_000, 0)]);
+    let t = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 1_000, 0)],
+        receives: vec![Bill::new(User::Bob, 991, 1)],
+        memo: Vec::new(),
+    };
+    assert!(DigitalCashSystem::try_next_state(&state, &t).is_ok());
+    assert_eq!(
+        accounting_breakdown(&state, &t),
+        Some(AccountingBreakdown {
+            spent: 1_000,
+            received: 991,
+            fee: 9,
+            burned: 0,
+        })
+    );
+
+    state.set_transfer_tax(true);
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&state, &t),
+        Err(TransferError::InsufficientTax {
+            required: 10,
+            paid: 9,
+        })
+    );
+}
+
+#[test]
+fn sm_5_tax_applies_to_signed_and_pooled_transfers() {
+    let mut state = State::from([Bill::new(User::Alice, 1_000, 0)]);
+    state.set_transfer_tax(true);
+    let signed = |amount| CashTransaction::SignedTransfer {
+        chain_id: 0,
+        signers: vec![User::Alice],
+        spends: vec![Bill::new(User::Alice, 1_000, 0)],
+        receives: vec![Bill::new(User::Bob, amount, 1)],
+    };
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&state, &signed(1_000)),
+        Err(TransferError::InsufficientTax {
+            required: 10,
+            paid: 0,
+        })
+    );
+    let end = DigitalCashSystem::try_next_state(&state, &signed(985)).unwrap();
+    assert_eq!(end.total_burned(), 10);
+    assert_eq!(
+        accounting_breakdown(&state, &signed(985)),
+        Some(AccountingBreakdown {
+            spent: 1_000,
+            received: 985,
+            fee: 5,
+            burned: 10,
+        })
+    );
+
+    let pooled = |amount| CashTransaction::PooledTransfer {
+        spends: vec![Bill::new(User::Alice, 1_000, 0)],
+        receives: vec![Bill::new(User::Bob, amount, 1)],
+    };
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&state, &pooled(995)),
+        Err(TransferError::InsufficientTax {
+            required: 10,
+            paid: 5,
+        })
+    );
+    // the tax is burned, and only what is left of the surplus reaches the pool
+    let end = DigitalCashSystem::try_next_state(&state, &pooled(985)).unwrap();
+    assert_eq!(end.total_burned(), 10);
+    assert_eq!(end.burn_pool(), 5);
+}
+
+#[test]
+fn sm_5_never_spent_finds_old_bills() {
+    let mut state = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 10, 1)]);
+    for _ in 0..3 {
+        state = DigitalCashSystem::next_state(&state, &CashTransaction::Tick);
+    }
+    state = DigitalCashSystem::next_state(
+        &state,
+        &CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Bob, 10, 1)],
+            receives: vec![Bill::new(User::Charlie, 10, 2)],
+            memo: Vec::new(),
+        },
+    );
+    state = DigitalCashSystem::next_state(&state, &CashTransaction::Tick);
+
+    let epoch = state.epoch();
+    assert_eq!(
+        state.never_spent(epoch, 2),
+        vec![Bill::new(User::Alice, 20, 0)]
+    );
+    assert_eq!(state.never_spent(epoch, 1).len(), 2);
+    assert!(state.never_spent(epoch, 5).is_empty());
+}
+
+#[test]
+fn sm_5_authority_may_mint() {
+    let start = State::with_authorities([User::Charlie]);
+    let end = DigitalCashSystem::try_next_state(
+        &start,
+        &CashTransaction::MintAndSend {
+            minter: User::Charlie,
+            recipient: User::Alice,
+            amount: 20,
+        },
+    )
+    .unwrap();
+    assert_eq!(end.balance_of(&User::Alice), 20);
+
+    let end = DigitalCashSystem::try_next_state(
+        &end,
+        &CashTransaction::Airdrop {
+            signer: User::Charlie,
+            recipients: vec![(User::Bob, 5)],
+        },
+    )
+    .unwrap();
+    assert_eq!(end.total_supply(), 25);
+}
+
+#[test]
+fn sm_5_non_authority_may_not_mint() {
+    let start = State::with_authorities([User::Charlie]);
+    assert_eq!(
+        DigitalCashSystem::try_next_state(
+            &start,
+            &CashTransaction::Mint {
+                minter: User::Alice,
+                amount: 20,
+            },
+        ),
+        Err(TransferError::Unauthorized)
+    );
+    assert_eq!(
+        DigitalCashSystem::try_next_state(
+            &start,
+            &CashTransaction::Airdrop {
+                signer: User::Bob,
+                recipients: vec![(User::Bob, 5)],
+            },
+        ),
+        Err(TransferError::Unauthorized)
+    );
 }
 
-impl FromIterator<Bill> for State {
-    fn from_iter<I: IntoIterator<Item = Bill>>(iter: I) -> Self {
-        let mut state = State::new();
+#[test]
+fn sm_5_bank_run_leaves_late_redeemers_unpaid() {
+    let mut state = State::from([
+        Bill::new(User::Alice, 20, 0),
+        Bill::new(User::Bob, 10, 1),
+        Bill::new(User::Bob, 5, 2),
+        Bill::new(User::Charlie, 10, 3),
+    ]);
+    state.burn_pool = 30;
+    let redeemers = [User::Bob, User::Alice, User::Charlie];
 
-        for i in iter {
-            state.add_bill(i)
-        }
-        state
-    }
-}
+    // Bob takes 15, leaving too little for Alice's 20, and so nothing for Charlie either
+    let (end, unpaid) = bank_run(&state, &redeemers, RedeemOrder::AsListed);
+    assert_eq!(unpaid, vec![User::Alice, User::Charlie]);
+    assert_eq!(end.balance_of(&User::Bob), 0);
+    assert_eq!(end.balance_of(&User::Alice), 20);
+    assert_eq!(end.burn_pool(), 15);
+    assert_eq!(end.total_burned(), 15);
 
-impl<const N: usize> From<[Bill; N]> for State {
-    fn from(value: [Bill; N]) -> Self {
-        State::from_iter(value)
-    }
-}
+    // served largest first, Alice gets out and Bob is the one who cannot
+    let (_, unpaid) = bank_run(&state, &redeemers, RedeemOrder::LargestFirst);
+    assert_eq!(unpaid, vec![User::Bob, User::Charlie]);
 
-/// The state transitions that users can make in a digital cash system
-pub enum CashTransaction {
-    /// Mint a single new bill owned by the minter
-    Mint { minter: User, amount: u64 },
-    /// Send some money from some users to other users. The money does not all need
-    /// to come from the same user, and it does not all need to go to the same user.
-    /// The total amount received must be less than or equal to the amount spent.
-    /// The discrepancy between the amount sent and received is destroyed. Therefore,
-    /// no dedicated burn transaction is required.
-    Transfer {
-        spends: Vec<Bill>,
-        receives: Vec<Bill>,
-    },
+    // served smallest first, Charlie and Bob both get out
+    let (end, unpaid) = bank_run(&state, &redeemers, RedeemOrder::SmallestFirst);
+    assert_eq!(unpaid, vec![User::Alice]);
+    assert_eq!(end.burn_pool(), 5);
 }
 
-/// We model this system as a state machine with two possible transitions
-impl StateMachine for DigitalCashSystem {
-    type State = State;
-    type Transition = CashTransaction;
-
-    fn next_state(starting_state: &Self::State, t: &Self::Transition) -> Self::State {
-        let mut next_state = starting_state.clone();
+#[test]
+fn sm_5_different_coin_selections_have_same_effect() {
+    let state = State::from([
+        Bill::new(User::Alice, 10, 0),
+        Bill::new(User::Alice, 10, 1),
+        Bill::new(User::Alice, 20, 2),
+    ]);
+    let two_small = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 10, 0), Bill::new(User::Alice, 10, 1)],
+        receives: vec![Bill::new(User::Bob, 15, 3), Bill::new(User::Alice, 5, 4)],
+        memo: Vec::new(),
+    };
+    let one_large = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 2)],
+        receives: vec![Bill::new(User::Alice, 5, 3), Bill::new(User::Bob, 15, 4)],
+        memo: Vec::new(),
+    };
+    assert!(same_effect(&state, &two_small, &one_large));
 
-        match t {
-            CashTransaction::Mint { minter, amount } => {
-                let bill = Bill {
-                    owner: minter.clone(),
-                    amount: amount.clone(),
-                    serial: starting_state.next_serial,
-                };
-                next_state.add_bill(bill);
-            }
-            CashTransaction::Transfer { spends, receives } => {
-                // if vec spends is empty, state stays the same
-                if spends.is_empty() {
-                    return next_state;
-                }
-                // if vec receives is empty, "burn" all the spent bills
-                if receives.is_empty() {
-                    next_state.bills.retain(|bill| !spends.contains(bill));
-                    return next_state;
-                }
-                // if total amount received overflows or spends and receives have the same bill, state stays the same
-                let mut total_amount_received: u64 = 0;
-                for bill in receives.iter() {
-                    if bill.amount == 0 || spends.contains(bill) {
-                        return next_state;
-                    }
-                    if let None = total_amount_received.checked_add(bill.amount) {
-                        return next_state;
-                    } else {
-                        total_amount_received += bill.amount;
-                    }
-                }
-                // if spending the bill that doesn't exist, state stays the same
-                let mut total_amount_spent = 0;
-                for bill in spends.iter() {
-                    if !next_state.bills.contains(bill) {
-                        return next_state;
-                    }
-                    total_amount_spent += bill.amount;
-                }
+    let pays_less = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 2)],
+        receives: vec![Bill::new(User::Alice, 10, 3), Bill::new(User::Bob, 10, 4)],
+        memo: Vec::new(),
+    };
+    assert!(!same_effect(&state, &two_small, &pays_less));
+}
 
-                // check for duplicates in spends
-                for i in 0..spends.len() {
-                    for j in (i + 1)..spends.len() {
-                        if spends[i] == spends[j] {
-                            return next_state;
-                        }
-                    }
-                }
-                // check for serial number already seen
-                for i in 0..spends.len() {
-                    for j in 0..receives.len() {
-                        if spends[i].serial == receives[j].serial {
-                            return next_state;
-                        }
-                    }
-                }
-                // check for serial number validity, if not valid, state stays the same
-                let mut j = 0;
-                for i in 0..receives.len() {
-                    if receives[i].serial != (next_state.next_serial + j) {
-                        return next_state;
-                    }
-                    j += 1;
-                }
-                // if total amount received is bigger than total amount spent, state stays the same
-                if total_amount_received > total_amount_spent {
-                    return next_state;
-                }
-                // all the conditions are satisifed, so we can insert received bills into hashset
-                // and remove spent bills from hashset
-                receives.iter().for_each(|bill| {
-                    next_state.add_bill(bill.clone());
-                });
-                spends.iter().for_each(|bill| {
-                    next_state.bills.remove(bill);
-                });
-            }
-        }
-        next_state
-    }
+#[test]
+fn sm_5_signed_transfer_applies_on_its_chain() {
+    let mut state = State::from([Bill::new(User::Alice, 20, 0)]);
+    state.set_chain_id(7);
+    let t = CashTransaction::SignedTransfer {
+        chain_id: 7,
+        signers: vec![User::Alice],
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![Bill::new(User::Bob, 20, 1)],
+    };
+    let end = DigitalCashSystem::try_next_state(&state, &t).unwrap();
+    assert_eq!(end.balance_of(&User::Bob), 20);
+    assert_eq!(end.chain_id(), 7);
 }
 
 #[test]
-fn sm_5_mint_new_cash() {
-    let start = State::new();
-    let end = DigitalCashSystem::next_state(
-        &start,
-        &CashTransaction::Mint {
-            minter: User::Alice,
-            amount: 20,
-        },
+fn sm_5_signed_transfer_replayed_on_fork_fails() {
+    let original = State::from([Bill::new(User::Alice, 20, 0)]);
+    let mut fork = original.clone();
+    fork.set_chain_id(1);
+    let t = CashTransaction::SignedTransfer {
+        chain_id: 0,
+        signers: vec![User::Alice],
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![Bill::new(User::Bob, 20, 1)],
+    };
+    assert!(DigitalCashSystem::try_next_state(&original, &t).is_ok());
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&fork, &t),
+        Err(TransferError::WrongChain)
     );
+}
 
-    let expected = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
-    assert_eq!(end, expected);
+#[test]
+fn sm_5_chain_bound_transactions_only_apply_on_their_chain() {
+    let original = State::from([Bill::new(User::Alice, 20, 0)]);
+    let mut fork = original.clone();
+    fork.set_chain_id(1);
+    let txs = [
+        CashTransaction::Mint {
+            minter: User::Bob,
+            amount: 10,
+        },
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Bob, 20, 1)],
+            memo: Vec::new(),
+        },
+        CashTransaction::Burn {
+            signer: User::Alice,
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+        },
+        CashTransaction::Tick,
+    ];
+    for transaction in txs {
+        let expected = DigitalCashSystem::try_next_state(&original, &transaction);
+        assert!(expected.is_ok());
+        let t = ChainBoundTransaction {
+            chain_id: 0,
+            transaction,
+        };
+        assert_eq!(
+            ChainBoundDigitalCashSystem::try_next_state(&original, &t),
+            expected
+        );
+        assert_eq!(
+            ChainBoundDigitalCashSystem::try_next_state(&fork, &t),
+            Err(TransferError::WrongChain)
+        );
+        assert_eq!(ChainBoundDigitalCashSystem::next_state(&fork, &t), fork);
+    }
 }
 
 #[test]
 fn sm_5_overflow_receives_fails() {
-    let start = State::from([Bill {
-        owner: User::Alice,
-        amount: 42,
-        serial: 0,
-    }]);
+    let start = State::from([Bill::new(User::Alice, 42, 0)]);
     let end = DigitalCashSystem::next_state(
         &start,
         &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Alice,
-                amount: 42,
-                serial: 0,
-            }],
+            spends: vec![Bill::new(User::Alice, 42, 0)],
             receives: vec![
-                Bill {
-                    owner: User::Alice,
-                    amount: u64::MAX,
-                    serial: 1,
-                },
-                Bill {
-                    owner: User::Alice,
-                    amount: 42,
-                    serial: 2,
-                },
+                Bill::new(User::Alice, u64::MAX, 1),
+                Bill::new(User::Alice, 42, 2),
             ],
+            memo: Vec::new(),
         },
     );
-    let expected = State::from([Bill {
-        owner: User::Alice,
-        amount: 42,
-        serial: 0,
-    }]);
+    let expected = State::from([Bill::new(User::Alice, 42, 0)]);
     assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_5_empty_spend_fails() {
-    let start = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
     let end = DigitalCashSystem::next_state(
         &start,
         &CashTransaction::Transfer {
             spends: vec![],
-            receives: vec![Bill {
-                owner: User::Alice,
-                amount: 15,
-                serial: 1,
-            }],
+            receives: vec![Bill::new(User::Alice, 15, 1)],
+            memo: Vec::new(),
         },
     );
-    let expected = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
+    let expected = State::from([Bill::new(User::Alice, 20, 0)]);
     assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_5_empty_receive_fails() {
-    let start = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
     let end = DigitalCashSystem::next_state(
         &start,
         &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Alice,
-                amount: 20,
-                serial: 0,
-            }],
+            spends: vec![Bill::new(User::Alice, 20, 0)],
             receives: vec![],
+            memo: Vec::new(),
         },
     );
-    let mut expected = State::from([]);
-    expected.set_serial(1);
+    let expected = State::from([Bill::new(User::Alice, 20, 0)]);
+    assert_eq!(end, expected);
+}
+
+#[test]
+fn sm_5_burn_attributes_value_to_signer() {
+    let start = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 5, 1)]);
+    let (end, events) = DigitalCashSystem::apply_with_events(
+        &start,
+        &CashTransaction::Burn {
+            signer: User::Alice,
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+        },
+    )
+    .unwrap();
+
+    let mut expected = State::from([Bill::new(User::Bob, 5, 1)]);
+    expected.set_serial(2).unwrap();
+    expected.total_burned = 20;
     assert_eq!(end, expected);
+    assert_eq!(end.total_burned(), 20);
+    assert_eq!(
+        events,
+        vec![CashEvent::ValueBurned {
+            signer: User::Alice,
+            amount: 20
+        }]
+    );
+}
+
+#[test]
+fn sm_5_burn_of_nonexistent_bill_fails() {
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
+    let burn = CashTransaction::Burn {
+        signer: User::Alice,
+        spends: vec![Bill::new(User::Alice, 20, 7)],
+    };
+
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&start, &burn),
+        Err(TransferError::NonexistentBill { serial: 7 })
+    );
+    assert_eq!(DigitalCashSystem::next_state(&start, &burn), start);
+}
+
+#[test]
+fn sm_5_burn_of_partly_nonexistent_bills_fails() {
+    let start = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 5, 1)]);
+    let burn = CashTransaction::Burn {
+        signer: User::Alice,
+        spends: vec![
+            Bill::new(User::Alice, 20, 0),
+            Bill::new(User::Alice, 20, 9),
+            Bill::new(User::Bob, 5, 1),
+        ],
+    };
+
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&start, &burn),
+        Err(TransferError::NonexistentBill { serial: 9 })
+    );
+    assert_eq!(DigitalCashSystem::next_state(&start, &burn), start);
+}
+
+#[test]
+fn sm_5_burn_of_duplicate_bills_fails() {
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
+    let burn = CashTransaction::Burn {
+        signer: User::Alice,
+        spends: vec![Bill::new(User::Alice, 20, 0), Bill::new(User::Alice, 20, 0)],
+    };
+
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&start, &burn),
+        Err(TransferError::DuplicateSpend { serial: 0 })
+    );
+    assert_eq!(DigitalCashSystem::next_state(&start, &burn), start);
 }
 
 #[test]
 fn sm_5_output_value_0_fails() {
-    let start = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
     let end = DigitalCashSystem::next_state(
         &start,
         &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Alice,
-                amount: 20,
-                serial: 0,
-            }],
-            receives: vec![Bill {
-                owner: User::Bob,
-                amount: 0,
-                serial: 1,
-            }],
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Bob, 0, 1)],
+            memo: Vec::new(),
         },
     );
-    let expected = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
+    let expected = State::from([Bill::new(User::Alice, 20, 0)]);
     assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_5_serial_number_already_seen_fails() {
-    let start = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
     let end = DigitalCashSystem::next_state(
         &start,
         &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Alice,
-                amount: 20,
-                serial: 0,
-            }],
-            receives: vec![Bill {
-                owner: User::Alice,
-                amount: 18,
-                serial: 0,
-            }],
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Alice, 18, 0)],
+            memo: Vec::new(),
         },
     );
-    let expected = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
+    let expected = State::from([Bill::new(User::Alice, 20, 0)]);
     assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_5_spending_and_receiving_same_bill_fails() {
-    let start = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
     let end = DigitalCashSystem::next_state(
         &start,
         &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Alice,
-                amount: 20,
-                serial: 0,
-            }],
-            receives: vec![Bill {
-                owner: User::Alice,
-                amount: 20,
-                serial: 0,
-            }],
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Alice, 20, 0)],
+            memo: Vec::new(),
         },
     );
-    let expected = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
+    let expected = State::from([Bill::new(User::Alice, 20, 0)]);
     assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_5_receiving_bill_with_incorrect_serial_fails() {
-    let start = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
     let end = DigitalCashSystem::next_state(
         &start,
         &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Alice,
-                amount: 20,
-                serial: 0,
-            }],
+            spends: vec![Bill::new(User::Alice, 20, 0)],
             receives: vec![
-                Bill {
-                    owner: User::Alice,
-                    amount: 10,
-                    serial: u64::MAX,
-                },
-                Bill {
-                    owner: User::Bob,
-                    amount: 10,
-                    serial: 4000,
-                },
+                Bill::new(User::Alice, 10, u64::MAX),
+                Bill::new(User::Bob, 10, 4000),
             ],
+            memo: Vec::new(),
         },
     );
-    let expected = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
+    let expected = State::from([Bill::new(User::Alice, 20, 0)]);
     assert_eq!(end, expected);
+
+    let result = DigitalCashSystem::try_next_state(
+        &start,
+        &CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![
+                Bill::new(User::Alice, 10, u64::MAX),
+                Bill::new(User::Bob, 10, 4000),
+            ],
+            memo: Vec::new(),
+        },
+    );
+    assert_eq!(
+        result,
+        Err(TransferError::NonContiguousSerial {
+            expected: 1,
+            got: u64::MAX
+        })
+    );
+
+    let result = DigitalCashSystem::try_next_state(
+        &start,
+        &CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![
+                Bill::new(User::Alice, 10, 1),
+                Bill::new(User::Bob, 10, 4000),
+            ],
+            memo: Vec::new(),
+        },
+    );
+    assert_eq!(
+        result,
+        Err(TransferError::NonContiguousSerial {
+            expected: 2,
+            got: 4000
+        })
+    );
+}
+
+#[test]
+fn sm_5_bad_serial_rejected_before_amounts_are_checked() {
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
+    let transfer = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![
+            Bill::new(User::Alice, 10, 1),
+            Bill::new(User::Bob, 10, 2),
+            Bill::new(User::Charlie, u64::MAX, 4),
+        ],
+        memo: Vec::new(),
+    };
+
+    // The bad serial is reported even though the amounts are also invalid
+    assert_eq!(
+        DigitalCashSystem::try_next_state(&start, &transfer),
+        Err(TransferError::NonContiguousSerial {
+            expected: 3,
+            got: 4
+        })
+    );
+    assert_eq!(DigitalCashSystem::next_state(&start, &transfer), start);
 }
 
 #[test]
 fn sm_5_spending_bill_with_incorrect_amount_fails() {
-    let start = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
     let end = DigitalCashSystem::next_state(
         &start,
         &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Alice,
-                amount: 40,
-                serial: 0,
-            }],
-            receives: vec![Bill {
-                owner: User::Bob,
-                amount: 40,
-                serial: 1,
-            }],
+            spends: vec![Bill::new(User::Alice, 40, 0)],
+            receives: vec![Bill::new(User::Bob, 40, 1)],
+            memo: Vec::new(),
         },
     );
-    let expected = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
+    let expected = State::from([Bill::new(User::Alice, 20, 0)]);
     assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_5_spending_same_bill_fails() {
-    let start = State::from([Bill {
-        owner: User::Alice,
-        amount: 40,
-        serial: 0,
-    }]);
+    let start = State::from([Bill::new(User::Alice, 40, 0)]);
     let end = DigitalCashSystem::next_state(
         &start,
         &CashTransaction::Transfer {
-            spends: vec![
-                Bill {
-                    owner: User::Alice,
-                    amount: 40,
-                    serial: 0,
-                },
-                Bill {
-                    owner: User::Alice,
-                    amount: 40,
-                    serial: 0,
-                },
-            ],
+            spends: vec![Bill::new(User::Alice, 40, 0), Bill::new(User::Alice, 40, 0)],
             receives: vec![
-                Bill {
-                    owner: User::Bob,
-                    amount: 20,
-                    serial: 1,
-                },
-                Bill {
-                    owner: User::Bob,
-                    amount: 20,
-                    serial: 2,
-                },
-                Bill {
-                    owner: User::Alice,
-                    amount: 40,
-                    serial: 3,
-                },
+                Bill::new(User::Bob, 20, 1),
+                Bill::new(User::Bob, 20, 2),
+                Bill::new(User::Alice, 40, 3),
             ],
+            memo: Vec::new(),
         },
     );
-    let expected = State::from([Bill {
-        owner: User::Alice,
-        amount: 40,
-        serial: 0,
-    }]);
+    let expected = State::from([Bill::new(User::Alice, 40, 0)]);
     assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_5_spending_more_than_bill_fails() {
     let start = State::from([
-        Bill {
-            owner: User::Alice,
-            amount: 40,
-            serial: 0,
-        },
-        Bill {
-            owner: User::Charlie,
-            amount: 42,
-            serial: 1,
-        },
+        Bill::new(User::Alice, 40, 0),
+        Bill::new(User::Charlie, 42, 1),
     ]);
     let end = DigitalCashSystem::next_state(
         &start,
         &CashTransaction::Transfer {
             spends: vec![
-                Bill {
-                    owner: User::Alice,
-                    amount: 40,
-                    serial: 0,
-                },
-                Bill {
-                    owner: User::Charlie,
-                    amount: 42,
-                    serial: 1,
-                },
+                Bill::new(User::Alice, 40, 0),
+                Bill::new(User::Charlie, 42, 1),
             ],
             receives: vec![
-                Bill {
-                    owner: User::Bob,
-                    amount: 20,
-                    serial: 2,
-                },
-                Bill {
-                    owner: User::Bob,
-                    amount: 20,
-                    serial: 3,
-                },
-                Bill {
-                    owner: User::Alice,
-                    amount: 52,
-                    serial: 4,
-                },
+                Bill::new(User::Bob, 20, 2),
+                Bill::new(User::Bob, 20, 3),
+                Bill::new(User::Alice, 52, 4),
             ],
+            memo: Vec::new(),
         },
     );
     let expected = State::from([
-        Bill {
-            owner: User::Alice,
-            amount: 40,
-            serial: 0,
-        },
-        Bill {
-            owner: User::Charlie,
-            amount: 42,
-            serial: 1,
-        },
+        Bill::new(User::Alice, 40, 0),
+        Bill::new(User::Charlie, 42, 1),
     ]);
     assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_5_spending_non_existent_bill_fails() {
-    let start = State::from([Bill {
-        owner: User::Alice,
-        amount: 32,
-        serial: 0,
-    }]);
+    let start = State::from([Bill::new(User::Alice, 32, 0)]);
     let end = DigitalCashSystem::next_state(
         &start,
         &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Bob,
-                amount: 1000,
-                serial: 32,
-            }],
-            receives: vec![Bill {
-                owner: User::Bob,
-                amount: 1000,
-                serial: 33,
-            }],
-        },
-    );
-    let expected = State::from([Bill {
-        owner: User::Alice,
-        amount: 32,
-        serial: 0,
-    }]);
+            spends: vec![Bill::new(User::Bob, 1000, 32)],
+            receives: vec![Bill::new(User::Bob, 1000, 33)],
+            memo: Vec::new(),
+        },
+    );
+    let expected = State::from([Bill::new(User::Alice, 32, 0)]);
     assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_5_spending_from_alice_to_all() {
-    let start = State::from([Bill {
-        owner: User::Alice,
-        amount: 42,
-        serial: 0,
-    }]);
+    let start = State::from([Bill::new(User::Alice, 42, 0)]);
     let end = DigitalCashSystem::next_state(
         &start,
         &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Alice,
-                amount: 42,
-                serial: 0,
-            }],
+            spends: vec![Bill::new(User::Alice, 42, 0)],
             receives: vec![
-                Bill {
-                    owner: User::Alice,
-                    amount: 10,
-                    serial: 1,
-                },
-                Bill {
-                    owner: User::Bob,
-                    amount: 10,
-                    serial: 2,
-                },
-                Bill {
-                    owner: User::Charlie,
-                    amount: 10,
-                    serial: 3,
-                },
+                Bill::new(User::Alice, 10, 1),
+                Bill::new(User::Bob, 10, 2),
+                Bill::new(User::Charlie, 10, 3),
             ],
+            memo: Vec::new(),
         },
     );
     let mut expected = State::from([
-        Bill {
-            owner: User::Alice,
-            amount: 10,
-            serial: 1,
-        },
-        Bill {
-            owner: User::Bob,
-            amount: 10,
-            serial: 2,
-        },
-        Bill {
-            owner: User::Charlie,
-            amount: 10,
-            serial: 3,
-        },
+        Bill::new(User::Alice, 10, 1),
+        Bill::new(User::Bob, 10, 2),
+        Bill::new(User::Charlie, 10, 3),
     ]);
-    expected.set_serial(4);
+    expected.set_serial(4).unwrap();
     assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_5_spending_from_bob_to_all() {
-    let start = State::from([Bill {
-        owner: User::Bob,
-        amount: 42,
-        serial: 0,
-    }]);
+    let start = State::from([Bill::new(User::Bob, 42, 0)]);
     let end = DigitalCashSystem::next_state(
         &start,
         &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Bob,
-                amount: 42,
-                serial: 0,
-            }],
+            spends: vec![Bill::new(User::Bob, 42, 0)],
             receives: vec![
-                Bill {
-                    owner: User::Alice,
-                    amount: 10,
-                    serial: 1,
-                },
-                Bill {
-                    owner: User::Bob,
-                    amount: 10,
-                    serial: 2,
-                },
-                Bill {
-                    owner: User::Charlie,
-                    amount: 22,
-                    serial: 3,
-                },
+                Bill::new(User::Alice, 10, 1),
+                Bill::new(User::Bob, 10, 2),
+                Bill::new(User::Charlie, 22, 3),
             ],
+            memo: Vec::new(),
         },
     );
     let mut expected = State::from([
-        Bill {
-            owner: User::Alice,
-            amount: 10,
-            serial: 1,
-        },
-        Bill {
-            owner: User::Bob,
-            amount: 10,
-            serial: 2,
-        },
-        Bill {
-            owner: User::Charlie,
-            amount: 22,
-            serial: 3,
-        },
+        Bill::new(User::Alice, 10, 1),
+        Bill::new(User::Bob, 10, 2),
+        Bill::new(User::Charlie, 22, 3),
     ]);
-    expected.set_serial(4);
+    expected.set_serial(4).unwrap();
     assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_5_spending_from_charlie_to_all() {
     let mut start = State::from([
-        Bill {
-            owner: User::Charlie,
-            amount: 68,
-            serial: 54,
-        },
-        Bill {
-            owner: User::Alice,
-            amount: 4000,
-            serial: 58,
-        },
+        Bill::new(User::Charlie, 68, 54),
+        Bill::new(User::Alice, 4000, 58),
     ]);
-    start.set_serial(59);
+    start.set_serial(59).unwrap();
     let end = DigitalCashSystem::next_state(
         &start,
         &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Charlie,
-                amount: 68,
-                serial: 54,
-            }],
+            spends: vec![Bill::new(User::Charlie, 68, 54)],
             receives: vec![
-                Bill {
-                    owner: User::Alice,
-                    amount: 42,
-                    serial: 59,
-                },
-                Bill {
-                    owner: User::Bob,
-                    amount: 5,
-                    serial: 60,
-                },
-                Bill {
-                    owner: User::Charlie,
-                    amount: 5,
-                    serial: 61,
-                },
+                Bill::new(User::Alice, 42, 59),
+                Bill::new(User::Bob, 5, 60),
+                Bill::new(User::Charlie, 5, 61),
             ],
+            memo: Vec::new(),
         },
     );
     let mut expected = State::from([
-        Bill {
-            owner: User::Alice,
-            amount: 4000,
-            serial: 58,
-        },
-        Bill {
-            owner: User::Alice,
-            amount: 42,
-            serial: 59,
-        },
-        Bill {
-            owner: User::Bob,
-            amount: 5,
-            serial: 60,
-        },
-        Bill {
-            owner: User::Charlie,
-            amount: 5,
-            serial: 61,
-        },
+        Bill::new(User::Alice, 4000, 58),
+        Bill::new(User::Alice, 42, 59),
+        Bill::new(User::Bob, 5, 60),
+        Bill::new(User::Charlie, 5, 61),
     ]);
-    expected.set_serial(62);
+    expected.set_serial(62).unwrap();
     assert_eq!(end, expected);
 }